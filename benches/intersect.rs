@@ -0,0 +1,103 @@
+//! Allocations and time per ray for intersecting a scene and for tracing full paths.
+//!
+//! Run with `cargo bench --bench intersect`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use misc::background::Gradient;
+use misc::bvh::BvhNode;
+use misc::camera::Camera;
+use misc::geometry::{Hittable, HittableList, Sphere};
+use misc::materials::{Dielectric, Lambertian, Material, Metal};
+use misc::ray::Ray;
+use misc::rays::ray_color;
+use misc::sampler::Sampler;
+use misc::vectors::{Point3, Vec3};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const RAYS: usize = 200_000;
+const MAX_DEPTH: u32 = 50;
+
+fn scene(rng: &mut Sampler) -> HittableList {
+    let mut world = HittableList::empty();
+    world.add(Arc::new(Sphere::new_f(0.0, -1000.0, 0.0, 1000.0, Arc::new(Lambertian::new_f(0.5, 0.5, 0.5)))));
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = Point3::new(a as f64 + 0.9 * rng.rand_f(), 0.2, b as f64 + 0.9 * rng.rand_f());
+            let choose = rng.rand_f();
+            let m: Arc<dyn Material> = if choose < 0.8 {
+                Arc::new(Lambertian::rand(rng))
+            } else if choose < 0.95 {
+                Arc::new(Metal::rand(rng))
+            } else {
+                Arc::new(Dielectric::new(1.5))
+            };
+            world.add(Arc::new(Sphere::new(&center, 0.2, m)));
+        }
+    }
+    world
+}
+
+fn measure<F: FnMut(&Ray)>(name: &str, rays: &[Ray], mut f: F) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for r in rays.iter() {
+        f(r);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let n = rays.len() as f64;
+    println!("{:<20} {:>10.3} allocations/ray {:>10.1} ns/ray",
+             name, allocations as f64 / n, elapsed.as_nanos() as f64 / n);
+}
+
+fn main() {
+    let mut rng = Sampler::new(21);
+    let list = scene(&mut rng);
+    let bvh = BvhNode::new(&list);
+
+    let camera = Camera::new(Point3::new(13.0, 2.0, 3.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 20.0, 1.5, 0.0, 10.0);
+    let rays: Vec<Ray> = (0..RAYS).map(|_| {
+        let (s, t) = (rng.rand_f(), rng.rand_f());
+        camera.get_ray(s, t, &mut rng)
+    }).collect();
+
+    measure("HittableList::hit", &rays, |r| {
+        black_box(list.hit(r, 0.001, f64::MAX).map(|h| h.t));
+    });
+    measure("BvhNode::hit", &rays, |r| {
+        black_box(bvh.hit(r, 0.001, f64::MAX).map(|h| h.t));
+    });
+
+    let background = Gradient::sky();
+    let lights = HittableList::empty();
+    measure("ray_color", &rays, |r| {
+        black_box(ray_color(r, &bvh, &lights, &background, MAX_DEPTH, &mut rng));
+    });
+}
@@ -0,0 +1,70 @@
+use crate::ray::Ray;
+use crate::vectors::{Point3, Vec3};
+
+/// Axis-aligned bounding box given by its minimal and maximal corners.
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Builds the box spanned by two arbitrary corners.
+    pub fn from_points(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            min: Vec3::new(a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])),
+            max: Vec3::new(a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2])),
+        }
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(a.min[0].min(b.min[0]), a.min[1].min(b.min[1]), a.min[2].min(b.min[2])),
+            max: Vec3::new(a.max[0].max(b.max[0]), a.max[1].max(b.max[1]), a.max[2].max(b.max[2])),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+    }
+
+    /// Index of the axis along which the box is the widest.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d[0] > d[1] && d[0] > d[2] {
+            0
+        } else if d[1] > d[2] {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction[a];
+            let mut t0 = (self.min[a] - r.origin[a]) * inv_d;
+            let mut t1 = (self.max[a] - r.origin[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,378 @@
+use std::f64::consts::PI;
+
+use crate::image::Image;
+use crate::m::{clamp, degree_to_rad};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vectors::{Axis, Color3, Vec3};
+
+/// Radiance reaching the camera from rays that leave the scene.
+pub trait Background: Send + Sync {
+    fn color(&self, r: &Ray) -> Color3;
+
+    /// Picks a direction towards the background roughly proportionally to its
+    /// radiance, together with its solid angle density. `None` when the
+    /// background does not support importance sampling.
+    fn sample(&self, _rng: &mut Sampler) -> Option<(Vec3, f64)> {
+        None
+    }
+
+    /// Solid angle density with which `sample` returns `direction`.
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        0.0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SolidColor {
+    color: Color3
+}
+
+impl SolidColor {
+    pub fn new(c: &Color3) -> SolidColor {
+        SolidColor { color: *c }
+    }
+
+    pub fn new_f(r: f64, g: f64, b: f64) -> SolidColor {
+        SolidColor { color: Color3::new(r, g, b) }
+    }
+}
+
+impl Background for SolidColor {
+    fn color(&self, _r: &Ray) -> Color3 {
+        self.color
+    }
+}
+
+/// Vertical blend from `bottom` (looking straight down) to `top` (looking straight up).
+#[derive(Debug, Copy, Clone)]
+pub struct Gradient {
+    bottom: Color3,
+    top: Color3,
+}
+
+impl Gradient {
+    pub fn new(bottom: &Color3, top: &Color3) -> Gradient {
+        Gradient { bottom: *bottom, top: *top }
+    }
+
+    /// White to light blue sky.
+    pub fn sky() -> Gradient {
+        Gradient::new(&Color3::new(1.0, 1.0, 1.0), &Color3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for Gradient {
+    fn color(&self, r: &Ray) -> Color3 {
+        let unit_direction = r.direction.unit_vec();
+        let t = 0.5 * (unit_direction[Axis::Y] + 1.0);
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
+
+/// Latitude-longitude panorama, +Y is up and the center of the image looks along -Z.
+///
+/// Texels are importance sampled by their luminance through a marginal
+/// distribution over rows and a conditional distribution inside every row.
+pub struct Equirectangular {
+    image: Image,
+    intensity: f64,
+    sin_rot: f64,
+    cos_rot: f64,
+    distribution: Option<Distribution2D>,
+}
+
+impl Equirectangular {
+    /// An image without pixels gives a black environment that is never sampled.
+    pub fn new(image: Image) -> Equirectangular {
+        let w = image.width();
+        let h = image.height();
+        let mut weights = Vec::with_capacity(w * h);
+        for y in 0..h {
+            // rows near the poles cover less solid angle
+            let sin_theta = (PI * (y as f64 + 0.5) / h as f64).sin();
+            for x in 0..w {
+                weights.push(luminance(&image.get(x, y)) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(w, h, weights);
+        Equirectangular { image, intensity: 1.0, sin_rot: 0.0, cos_rot: 1.0, distribution }
+    }
+
+    /// Turns the panorama around the vertical axis.
+    pub fn set_rotation(&mut self, degrees: f64) {
+        let rad = degree_to_rad(degrees);
+        self.sin_rot = rad.sin();
+        self.cos_rot = rad.cos();
+    }
+
+    /// Scales the radiance of every texel.
+    pub fn set_intensity(&mut self, intensity: f64) {
+        self.intensity = intensity;
+    }
+
+    // maps a world direction to panorama coordinates
+    fn direction_to_uv(&self, direction: &Vec3) -> (f64, f64) {
+        let d = direction.unit_vec();
+        let x = self.cos_rot * d[Axis::X] - self.sin_rot * d[Axis::Z];
+        let z = self.sin_rot * d[Axis::X] + self.cos_rot * d[Axis::Z];
+        let phi = x.atan2(-z);
+        let theta = clamp(d[Axis::Y], -1.0, 1.0).acos();
+        (0.5 + phi / (2.0 * PI), theta / PI)
+    }
+
+    // maps panorama coordinates back to a world direction
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI;
+        let theta = v * PI;
+        let x = theta.sin() * phi.sin();
+        let z = -theta.sin() * phi.cos();
+        Vec3::new(self.cos_rot * x + self.sin_rot * z, theta.cos(), -self.sin_rot * x + self.cos_rot * z)
+    }
+
+    // bilinear lookup, wrapping around horizontally and clamping at the poles
+    fn lookup(&self, u: f64, v: f64) -> Color3 {
+        let w = self.image.width();
+        let h = self.image.height();
+        if w == 0 || h == 0 {
+            return Color3::zero();
+        }
+        let x = u * w as f64 - 0.5;
+        let y = clamp(v * h as f64 - 0.5, 0.0, h as f64 - 1.0);
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let x0 = (x0 as i64).rem_euclid(w as i64) as usize;
+        let x1 = (x0 + 1) % w;
+        let y0 = y0 as usize;
+        let y1 = (y0 + 1).min(h - 1);
+
+        (1.0 - fy) * ((1.0 - fx) * self.image.get(x0, y0) + fx * self.image.get(x1, y0))
+            + fy * ((1.0 - fx) * self.image.get(x0, y1) + fx * self.image.get(x1, y1))
+    }
+}
+
+impl Background for Equirectangular {
+    fn color(&self, r: &Ray) -> Color3 {
+        let (u, v) = self.direction_to_uv(&r.direction);
+        self.intensity * self.lookup(u, v)
+    }
+
+    fn sample(&self, rng: &mut Sampler) -> Option<(Vec3, f64)> {
+        let distribution = self.distribution.as_ref()?;
+        let (u, v, pdf_uv) = distribution.sample(rng.rand_f(), rng.rand_f());
+        let sin_theta = (v * PI).sin();
+        if pdf_uv <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some((self.uv_to_direction(u, v), pdf_uv / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.0,
+        };
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+fn luminance(c: &Color3) -> f64 {
+    0.2126 * c[Axis::X] + 0.7152 * c[Axis::Y] + 0.0722 * c[Axis::Z]
+}
+
+/// Piecewise constant density over the unit square made of `width` x `height` cells.
+struct Distribution2D {
+    width: usize,
+    height: usize,
+    weights: Vec<f64>,
+    // cumulative distribution inside every row, `width + 1` entries per row
+    conditional: Vec<f64>,
+    // cumulative distribution of the rows, `height + 1` entries
+    marginal: Vec<f64>,
+    mean: f64,
+}
+
+impl Distribution2D {
+    // `None` when the grid is empty or every weight is zero
+    fn new(width: usize, height: usize, weights: Vec<f64>) -> Option<Distribution2D> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let mut conditional = Vec::with_capacity((width + 1) * height);
+        let mut marginal = Vec::with_capacity(height + 1);
+        marginal.push(0.0);
+
+        for row in weights.chunks(width) {
+            let start = conditional.len();
+            conditional.push(0.0);
+            let mut acc = 0.0;
+            for w in row {
+                acc += w.max(0.0);
+                conditional.push(acc);
+            }
+            if acc > 0.0 {
+                conditional[start..].iter_mut().for_each(|c| *c /= acc);
+            }
+            marginal.push(marginal[marginal.len() - 1] + acc);
+        }
+
+        let total = marginal[height];
+        if total <= 0.0 {
+            return None;
+        }
+        marginal.iter_mut().for_each(|m| *m /= total);
+        let mean = total / (width * height) as f64;
+
+        Some(Distribution2D { width, height, weights, conditional, marginal, mean })
+    }
+
+    // returns the sampled point and its density with respect to the unit square
+    fn sample(&self, u1: f64, u2: f64) -> (f64, f64, f64) {
+        let (y, fy) = sample_cdf(&self.marginal, u1);
+        let row = &self.conditional[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let (x, fx) = sample_cdf(row, u2);
+
+        let pdf = self.weights[y * self.width + x].max(0.0) / self.mean;
+        ((x as f64 + fx) / self.width as f64, (y as f64 + fy) / self.height as f64, pdf)
+    }
+
+    fn pdf(&self, u: f64, v: f64) -> f64 {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.weights[y * self.width + x].max(0.0) / self.mean
+    }
+}
+
+// finds the cell of a cumulative distribution containing `u` and the relative offset of `u` inside it
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    let cells = cdf.len() - 1;
+    let idx = cdf.partition_point(|&c| c <= u).saturating_sub(1).min(cells - 1);
+    let width = cdf[idx + 1] - cdf[idx];
+    let offset = if width > 0.0 { clamp((u - cdf[idx]) / width, 0.0, 1.0) } else { 0.5 };
+    (idx, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(direction: Vec3) -> Ray {
+        Ray::new(&Vec3::new(1.0, 2.0, 3.0), &direction)
+    }
+
+    fn close(a: Color3, b: Color3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    // 8 x 4 panorama where every texel has its own color
+    fn panorama() -> Image {
+        let pixels = (0..32).map(|i| Color3::new((i % 8) as f64, (i / 8) as f64, 1.0)).collect();
+        Image::new(8, 4, pixels)
+    }
+
+    #[test]
+    fn solid_color_ignores_the_direction() {
+        let bg = SolidColor::new_f(0.1, 0.2, 0.3);
+        for d in [Vec3::new(0.0, 1.0, 0.0), Vec3::new(3.0, -2.0, 1.0), Vec3::new(0.0, 0.0, -0.5)] {
+            assert_eq!(bg.color(&ray(d)), Color3::new(0.1, 0.2, 0.3));
+        }
+        assert!(bg.sample(&mut Sampler::new(1)).is_none());
+        assert_eq!(bg.pdf(&Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn sky_blends_from_white_to_blue() {
+        let sky = Gradient::sky();
+        assert!(close(sky.color(&ray(Vec3::new(0.0, 5.0, 0.0))), Color3::new(0.5, 0.7, 1.0)));
+        assert!(close(sky.color(&ray(Vec3::new(0.0, -2.0, 0.0))), Color3::new(1.0, 1.0, 1.0)));
+        assert!(close(sky.color(&ray(Vec3::new(1.0, 0.0, 1.0))), Color3::new(0.75, 0.85, 1.0)));
+    }
+
+    #[test]
+    fn panorama_texel_centers_map_to_their_texels() {
+        let image = panorama();
+        let mut env = Equirectangular::new(image.clone());
+        env.set_intensity(2.0);
+        for y in 0..4 {
+            for x in 0..8 {
+                let (u, v) = ((x as f64 + 0.5) / 8.0, (y as f64 + 0.5) / 4.0);
+                let d = env.uv_to_direction(u, v);
+                let (u2, v2) = env.direction_to_uv(&d);
+                assert!((u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9);
+                assert!(close(env.color(&ray(d)), 2.0 * image.get(x, y)));
+            }
+        }
+        // the middle of the image looks along -Z, the top row up
+        assert!((env.direction_to_uv(&Vec3::new(0.0, 0.0, -1.0)).0 - 0.5).abs() < 1e-9);
+        assert!(env.direction_to_uv(&Vec3::new(0.0, 1.0, 0.0)).1.abs() < 1e-9);
+    }
+
+    #[test]
+    fn rotation_turns_the_panorama_around_y() {
+        let plain = Equirectangular::new(panorama());
+        let mut turned = Equirectangular::new(panorama());
+        turned.set_rotation(90.0);
+        // what was seen towards +X is now seen towards -Z
+        let dirs = [(Vec3::new(0.0, 0.2, -1.0), Vec3::new(1.0, 0.2, 0.0)),
+                    (Vec3::new(1.0, -0.5, 0.0), Vec3::new(0.0, -0.5, 1.0)),
+                    (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0))];
+        for (d, original) in dirs.iter() {
+            assert!(close(turned.color(&ray(*d)), plain.color(&ray(*original))), "{:?}", d);
+        }
+    }
+
+    // dim 16 x 8 panorama with one very bright texel at (5, 2)
+    fn spot() -> Image {
+        let mut pixels = vec![Color3::new(0.01, 0.01, 0.01); 16 * 8];
+        pixels[2 * 16 + 5] = Color3::new(1000.0, 1000.0, 1000.0);
+        Image::new(16, 8, pixels)
+    }
+
+    // checks the sampled densities and returns the mean of the sampled directions
+    fn sample_spot(env: &Equirectangular) -> Vec3 {
+        let mut rng = Sampler::new(7);
+        let mut sum = Vec3::zero();
+        let mut in_spot = 0;
+        for _ in 0..2000 {
+            let (dir, pdf) = env.sample(&mut rng).unwrap();
+            assert!((pdf - env.pdf(&dir)).abs() <= 1e-6 * pdf, "{} vs {}", pdf, env.pdf(&dir));
+            let (u, v) = env.direction_to_uv(&dir);
+            if ((u * 16.0) as usize, (v * 8.0) as usize) == (5, 2) {
+                in_spot += 1;
+                sum += dir.unit_vec();
+            }
+        }
+        assert!(in_spot > 1950, "only {} samples hit the bright texel", in_spot);
+        sum.unit_vec()
+    }
+
+    #[test]
+    fn sampling_finds_the_bright_texel() {
+        let plain = Equirectangular::new(spot());
+        let center = plain.uv_to_direction(5.5 / 16.0, 2.5 / 8.0);
+        assert!(sample_spot(&plain).dot(center) > 0.99);
+
+        // turning by 90 degrees brings +X to -Z and +Z to +X
+        let mut turned = Equirectangular::new(spot());
+        turned.set_rotation(90.0);
+        let rotated = Vec3::new(center[Axis::Z], center[Axis::Y], -center[Axis::X]);
+        assert!(sample_spot(&turned).dot(rotated) > 0.99);
+    }
+
+    #[test]
+    fn empty_panorama_is_black_and_never_sampled() {
+        let env = Equirectangular::new(Image::new(0, 0, Vec::new()));
+        assert_eq!(env.color(&ray(Vec3::new(0.0, 1.0, 0.0))), Color3::zero());
+        assert!(env.sample(&mut Sampler::new(1)).is_none());
+        assert_eq!(env.pdf(&Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::geometry::{HitRecord, Hittable, HittableList};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vectors::{Point3, Vec3};
+
+const SAH_BINS: usize = 16;
+
+/// Bounding volume hierarchy over the elements of a `HittableList`.
+///
+/// Split planes are chosen with the surface area heuristic evaluated over
+/// `SAH_BINS` buckets of primitive centroids along every axis.
+///
+/// Used as a light list, every node picks either child with even odds, so
+/// unlike a `HittableList` primitives are not all equally likely.
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: Aabb,
+}
+
+struct Primitive {
+    object: Arc<dyn Hittable>,
+    bbox: Aabb,
+    centroid: [f64; 3],
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    count: usize,
+    bbox: Option<Aabb>,
+}
+
+impl BvhNode {
+    /// Panics if the list is empty or contains an object without a bounding box.
+    pub fn new(list: &HittableList) -> BvhNode {
+        let mut primitives: Vec<Primitive> = list.elements().iter()
+            .map(|object| {
+                let bbox = object.bounding_box().expect("No bounding box in BvhNode constructor");
+                let c = bbox.centroid();
+                Primitive { object: object.clone(), bbox, centroid: [c[0], c[1], c[2]] }
+            })
+            .collect();
+        assert!(!primitives.is_empty(), "BvhNode requires at least one object");
+
+        if primitives.len() == 1 {
+            let only = primitives.pop().unwrap();
+            return BvhNode { left: only.object.clone(), right: only.object, bbox: only.bbox };
+        }
+        BvhNode::build(&mut primitives)
+    }
+
+    fn build(primitives: &mut [Primitive]) -> BvhNode {
+        let bbox = primitives.iter().skip(1)
+            .fold(primitives[0].bbox, |acc, p| Aabb::surrounding(&acc, &p.bbox));
+
+        if primitives.len() == 2 {
+            return BvhNode { left: primitives[0].object.clone(), right: primitives[1].object.clone(), bbox };
+        }
+
+        let mid = BvhNode::partition(primitives);
+        let (l, r) = primitives.split_at_mut(mid);
+        BvhNode { left: BvhNode::subtree(l), right: BvhNode::subtree(r), bbox }
+    }
+
+    fn subtree(primitives: &mut [Primitive]) -> Arc<dyn Hittable> {
+        if primitives.len() == 1 {
+            primitives[0].object.clone()
+        } else {
+            Arc::new(BvhNode::build(primitives))
+        }
+    }
+
+    // Reorders the primitives so that both halves of the best SAH split are contiguous
+    // and returns the index of the first primitive of the right half.
+    fn partition(primitives: &mut [Primitive]) -> usize {
+        let mut c_min = [f64::MAX; 3];
+        let mut c_max = [f64::MIN; 3];
+        for p in primitives.iter() {
+            for a in 0..3 {
+                c_min[a] = c_min[a].min(p.centroid[a]);
+                c_max[a] = c_max[a].max(p.centroid[a]);
+            }
+        }
+
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            let extent = c_max[axis] - c_min[axis];
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins = [Bin { count: 0, bbox: None }; SAH_BINS];
+            for p in primitives.iter() {
+                let b = bin_index(p.centroid[axis], c_min[axis], extent);
+                bins[b].count += 1;
+                bins[b].bbox = Some(grow(bins[b].bbox, &p.bbox));
+            }
+
+            // areas and counts of everything to the right of every split plane
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0; SAH_BINS];
+            let mut acc: Option<Aabb> = None;
+            let mut count = 0;
+            for b in (1..SAH_BINS).rev() {
+                if let Some(bbox) = bins[b].bbox {
+                    acc = Some(grow(acc, &bbox));
+                }
+                count += bins[b].count;
+                right_area[b] = acc.map_or(0.0, |a| a.surface_area());
+                right_count[b] = count;
+            }
+
+            let mut acc: Option<Aabb> = None;
+            let mut count = 0;
+            for split in 1..SAH_BINS {
+                if let Some(bbox) = bins[split - 1].bbox {
+                    acc = Some(grow(acc, &bbox));
+                }
+                count += bins[split - 1].count;
+                if count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+                let cost = count as f64 * acc.map_or(0.0, |a| a.surface_area())
+                    + right_count[split] as f64 * right_area[split];
+                let better = match best {
+                    Some((best_cost, _, _)) => cost < best_cost,
+                    None => true,
+                };
+                if better {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        match best {
+            Some((_, axis, split)) => {
+                let extent = c_max[axis] - c_min[axis];
+                let mut mid = 0;
+                for i in 0..primitives.len() {
+                    if bin_index(primitives[i].centroid[axis], c_min[axis], extent) < split {
+                        primitives.swap(i, mid);
+                        mid += 1;
+                    }
+                }
+                mid
+            }
+            // all centroids coincide, any split is as good as another
+            None => primitives.len() / 2,
+        }
+    }
+}
+
+fn bin_index(c: f64, min: f64, extent: f64) -> usize {
+    (((c - min) / extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+fn grow(acc: Option<Aabb>, bbox: &Aabb) -> Aabb {
+    match acc {
+        Some(acc) => Aabb::surrounding(&acc, bbox),
+        None => *bbox,
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(ray, t_min, t_max);
+        let hit_right = self.right.hit(ray, t_min, hit_left.as_ref().map_or(t_max, |h| h.t));
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if !self.bbox.hit(&Ray::new(origin, direction), 0.001, f64::MAX) {
+            return 0.0;
+        }
+        0.5 * self.left.pdf_value(origin, direction) + 0.5 * self.right.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        if rng.rand_f() < 0.5 {
+            self.left.random(origin, rng)
+        } else {
+            self.right.random(origin, rng)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Sphere, Triangle};
+    use crate::materials::Lambertian;
+
+    #[test]
+    fn hits_match_linear_list() {
+        let mut rng = Sampler::new(11);
+        let mat = Arc::new(Lambertian::new_f(0.5, 0.5, 0.5));
+        let mut list = HittableList::empty();
+        for _ in 0..200 {
+            let c = Vec3::random_mm(&mut rng, -10.0, 10.0);
+            if rng.rand_f() < 0.5 {
+                list.add(Arc::new(Sphere::new(&c, rng.rand_f_mm(0.1, 1.0), mat.clone())));
+            } else {
+                let a = c + Vec3::random_mm(&mut rng, -1.0, 1.0);
+                let b = c + Vec3::random_mm(&mut rng, -1.0, 1.0);
+                list.add(Arc::new(Triangle::new(&c, &a, &b, mat.clone())));
+            }
+        }
+        let bvh = BvhNode::new(&list);
+
+        for _ in 0..2000 {
+            let origin = Vec3::random_mm(&mut rng, -15.0, 15.0);
+            let target: Point3 = Vec3::random_mm(&mut rng, -10.0, 10.0);
+            let r = Ray::new(&origin, &(target - origin));
+            match (list.hit(&r, 0.001, f64::MAX), bvh.hit(&r, 0.001, f64::MAX)) {
+                (None, None) => {}
+                (Some(a), Some(b)) => {
+                    assert_eq!(a.t, b.t);
+                    assert_eq!(a.p, b.p);
+                    assert_eq!(a.normal, b.normal);
+                }
+                (a, b) => panic!("list hit {:?}, bvh hit {:?}", a.map(|h| h.t), b.map(|h| h.t)),
+            }
+        }
+    }
+
+    #[test]
+    fn light_sampling_is_a_normalized_density() {
+        let mut rng = Sampler::new(5);
+        let mat = Arc::new(Lambertian::new_f(0.5, 0.5, 0.5));
+        let mut list = HittableList::empty();
+        for i in 0..7 {
+            let angle = i as f64;
+            let c = Point3::new(4.0 * angle.cos(), (i as f64 - 3.0) * 0.8, 4.0 * angle.sin());
+            list.add(Arc::new(Sphere::new(&c, 1.0 + 0.1 * i as f64, mat.clone())));
+        }
+        let bvh = BvhNode::new(&list);
+        let origin = Point3::new(0.1, 0.2, 0.3);
+
+        // every sampled direction must be one the density accounts for
+        for _ in 0..1000 {
+            let d = bvh.random(&origin, &mut rng);
+            assert!(bvh.pdf_value(&origin, &d) > 0.0);
+        }
+
+        // integrated over the sphere of directions the density gives one
+        let n = 200_000;
+        let total: f64 = (0..n)
+            .map(|_| 4.0 * std::f64::consts::PI * bvh.pdf_value(&origin, &Vec3::random_unit_vector(&mut rng)))
+            .sum();
+        let integral = total / n as f64;
+        assert!((integral - 1.0).abs() < 0.03, "integral {}", integral);
+    }
+}
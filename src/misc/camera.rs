@@ -1,5 +1,6 @@
 use crate::m::degree_to_rad;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vectors::{Point3, Vec3, Axis};
 
 pub struct Camera {
@@ -41,8 +42,8 @@ impl Camera {
         Camera { origin, ll_corner, horizontal, vertical, w, u, v, lens_radius }
     }
 
-    pub fn get_ray(self: &Self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    pub fn get_ray(&self, s: f64, t: f64, rng: &mut Sampler) -> Ray {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk(rng);
         let offset = self.u * rd[Axis::X] + self.v * rd[Axis::Y];
         Ray::new(&(self.origin + offset),
                  &(self.ll_corner + s * self.horizontal + t * self.vertical - self.origin - offset))
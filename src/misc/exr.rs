@@ -0,0 +1,404 @@
+use std::io::{self, Write};
+
+use crate::image::Image;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Rle,
+}
+
+/// Single-part scanline OpenEXR image made of named channels.
+///
+/// Layers follow the usual `layer.channel` naming, the unnamed layer holds the
+/// beauty pass as plain `R`, `G` and `B`.
+pub struct ExrImage {
+    width: usize,
+    height: usize,
+    channels: Vec<Channel>,
+}
+
+struct Channel {
+    name: String,
+    pixel_type: PixelType,
+    values: Vec<f32>,
+}
+
+impl ExrImage {
+    pub fn new(width: usize, height: usize) -> ExrImage {
+        ExrImage { width, height, channels: Vec::new() }
+    }
+
+    /// Adds the `R`, `G` and `B` channels of `image` under the layer `name`,
+    /// an empty name stores them as the beauty pass.
+    pub fn add_layer(&mut self, name: &str, image: &Image, pixel_type: PixelType) {
+        assert!(image.width() == self.width && image.height() == self.height, "Layer size does not match the image");
+        let prefix = if name.is_empty() { String::new() } else { format!("{}.", name) };
+        for (idx, suffix) in ["R", "G", "B"].iter().enumerate() {
+            let values = image.pixels().iter().map(|px| px[idx] as f32).collect();
+            self.push(format!("{}{}", prefix, suffix), pixel_type, values);
+        }
+    }
+
+    /// Adds a single channel such as `Z` or `depth.Z`, values go row by row from the top.
+    pub fn add_channel(&mut self, name: &str, values: &[f64], pixel_type: PixelType) {
+        assert_eq!(values.len(), self.width * self.height, "Channel size does not match the image");
+        self.push(name.to_string(), pixel_type, values.iter().map(|&v| v as f32).collect());
+    }
+
+    fn push(&mut self, name: String, pixel_type: PixelType, values: Vec<f32>) {
+        assert!(!name.is_empty(), "Channel name can not be empty");
+        self.channels.retain(|c| c.name != name);
+        self.channels.push(Channel { name, pixel_type, values });
+        // readers expect the channel list in alphabetical order
+        self.channels.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, compression: Compression) -> io::Result<()> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&20000630u32.to_le_bytes());
+        let long_names = self.channels.iter().any(|c| c.name.len() > 31);
+        header.extend_from_slice(&(2u32 | if long_names { 0x400 } else { 0 }).to_le_bytes());
+
+        let mut chlist = Vec::new();
+        for c in self.channels.iter() {
+            chlist.extend_from_slice(c.name.as_bytes());
+            chlist.push(0);
+            chlist.extend_from_slice(&(if c.pixel_type == PixelType::Half { 1i32 } else { 2i32 }).to_le_bytes());
+            // pLinear and three reserved bytes, then x and y sampling
+            chlist.extend_from_slice(&[0, 0, 0, 0]);
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+        }
+        chlist.push(0);
+
+        let mut window = Vec::new();
+        for v in [0, 0, self.width as i32 - 1, self.height as i32 - 1] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+
+        attribute(&mut header, "channels", "chlist", &chlist);
+        attribute(&mut header, "compression", "compression", &[if compression == Compression::None { 0 } else { 1 }]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        header.push(0);
+
+        // one scanline per chunk for both supported compressions
+        let mut chunks = Vec::with_capacity(self.height);
+        let mut raw = Vec::new();
+        for y in 0..self.height {
+            raw.clear();
+            for c in self.channels.iter() {
+                for &v in c.values[y * self.width..(y + 1) * self.width].iter() {
+                    match c.pixel_type {
+                        PixelType::Half => raw.extend_from_slice(&f32_to_f16(v).to_le_bytes()),
+                        PixelType::Float => raw.extend_from_slice(&v.to_le_bytes()),
+                    }
+                }
+            }
+            let data = match compression {
+                Compression::None => raw.clone(),
+                Compression::Rle => {
+                    let packed = rle_compress(&raw);
+                    // data that does not shrink is stored as it is
+                    if packed.len() < raw.len() { packed } else { raw.clone() }
+                }
+            };
+            chunks.push(data);
+        }
+
+        w.write_all(&header)?;
+        let mut offset = (header.len() + 8 * chunks.len()) as u64;
+        for chunk in chunks.iter() {
+            w.write_all(&offset.to_le_bytes())?;
+            offset += 8 + chunk.len() as u64;
+        }
+        for (y, chunk) in chunks.iter().enumerate() {
+            w.write_all(&(y as i32).to_le_bytes())?;
+            w.write_all(&(chunk.len() as i32).to_le_bytes())?;
+            w.write_all(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Rounds to the nearest half float, ties to even; overflow goes to infinity.
+pub fn f32_to_f16(v: f32) -> u16 {
+    let x = v.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xff) as i32;
+    let mant = x & 0x7f_ffff;
+
+    if exp == 255 {
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 31 {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let mut h = m >> shift;
+        let rem = m & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rem > half || (rem == half && h & 1 == 1) {
+            h += 1;
+        }
+        return sign | h as u16;
+    }
+
+    let mut h = ((e as u32) << 10) | (mant >> 13);
+    let rem = mant & 0x1fff;
+    if rem > 0x1000 || (rem == 0x1000 && h & 1 == 1) {
+        // a carry out of the mantissa correctly bumps the exponent
+        h += 1;
+    }
+    sign | h as u16
+}
+
+const MIN_RUN_LENGTH: usize = 3;
+const MAX_RUN_LENGTH: usize = 127;
+
+// splits the bytes into even and odd halves, delta encodes them and run-length encodes the result
+fn rle_compress(raw: &[u8]) -> Vec<u8> {
+    let n = raw.len();
+    let mut tmp = vec![0u8; n];
+    let (first, second) = tmp.split_at_mut(n.div_ceil(2));
+    for (i, &b) in raw.iter().enumerate() {
+        if i % 2 == 0 {
+            first[i / 2] = b;
+        } else {
+            second[i / 2] = b;
+        }
+    }
+
+    let mut p = tmp.first().copied().unwrap_or(0);
+    for t in tmp.iter_mut().skip(1) {
+        let d = t.wrapping_sub(p).wrapping_add(128);
+        p = *t;
+        *t = d;
+    }
+
+    let mut out = Vec::with_capacity(n);
+    let mut run_start = 0;
+    let mut run_end = 1;
+    while run_start < n {
+        while run_end < n && tmp[run_start] == tmp[run_end] && run_end - run_start - 1 < MAX_RUN_LENGTH {
+            run_end += 1;
+        }
+        if run_end - run_start >= MIN_RUN_LENGTH {
+            out.push((run_end - run_start - 1) as u8);
+            out.push(tmp[run_start]);
+            run_start = run_end;
+        } else {
+            while run_end < n
+                && ((run_end + 1 >= n || tmp[run_end] != tmp[run_end + 1])
+                    || (run_end + 2 >= n || tmp[run_end + 1] != tmp[run_end + 2]))
+                && run_end - run_start < MAX_RUN_LENGTH {
+                run_end += 1;
+            }
+            out.push((run_start as isize - run_end as isize) as u8);
+            out.extend_from_slice(&tmp[run_start..run_end]);
+            run_start = run_end;
+        }
+        run_end += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::Color3;
+
+    fn rle_uncompress(packed: &[u8]) -> Vec<u8> {
+        let mut tmp = Vec::new();
+        let mut i = 0;
+        while i < packed.len() {
+            let count = packed[i] as i8;
+            if count < 0 {
+                let n = -(count as isize) as usize;
+                tmp.extend_from_slice(&packed[i + 1..i + 1 + n]);
+                i += 1 + n;
+            } else {
+                tmp.resize(tmp.len() + count as usize + 1, packed[i + 1]);
+                i += 2;
+            }
+        }
+        for k in 1..tmp.len() {
+            tmp[k] = tmp[k - 1].wrapping_add(tmp[k]).wrapping_sub(128);
+        }
+        let (first, second) = tmp.split_at(tmp.len().div_ceil(2));
+        (0..tmp.len()).map(|i| if i % 2 == 0 { first[i / 2] } else { second[i / 2] }).collect()
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, n: usize) -> &'a [u8] {
+            let out = &self.bytes[self.pos..self.pos + n];
+            self.pos += n;
+            out
+        }
+
+        fn string(&mut self) -> String {
+            let len = self.bytes[self.pos..].iter().position(|&b| b == 0).unwrap();
+            let s = String::from_utf8(self.take(len).to_vec()).unwrap();
+            self.pos += 1;
+            s
+        }
+
+        fn i32(&mut self) -> i32 {
+            let b = self.take(4);
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        }
+
+        fn u64(&mut self) -> u64 {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(self.take(8));
+            u64::from_le_bytes(b)
+        }
+    }
+
+    // name, type and raw value of every header attribute
+    fn attributes(r: &mut Reader) -> Vec<(String, String, Vec<u8>)> {
+        let mut out = Vec::new();
+        loop {
+            let name = r.string();
+            if name.is_empty() {
+                return out;
+            }
+            let kind = r.string();
+            let len = r.i32() as usize;
+            out.push((name, kind, r.take(len).to_vec()));
+        }
+    }
+
+    fn sample() -> ExrImage {
+        let (w, h) = (5, 3);
+        let pixels = (0..w * h).map(|i| Color3::new(i as f64, 0.5, if i < 7 { 1.0 } else { -2.0 })).collect();
+        let mut exr = ExrImage::new(w, h);
+        exr.add_layer("", &Image::new(w, h, pixels), PixelType::Half);
+        exr.add_channel("Z", &vec![3.0; w * h], PixelType::Float);
+        exr
+    }
+
+    fn check(compression: Compression) {
+        let exr = sample();
+        let mut out = Vec::new();
+        exr.write(&mut out, compression).unwrap();
+
+        let mut r = Reader { bytes: &out, pos: 0 };
+        assert_eq!(r.i32(), 20000630);
+        assert_eq!(r.i32(), 2);
+        let attrs = attributes(&mut r);
+        let names: Vec<&str> = attrs.iter().map(|(n, _, _)| n.as_str()).collect();
+        assert_eq!(names, vec![
+            "channels", "compression", "dataWindow", "displayWindow", "lineOrder",
+            "pixelAspectRatio", "screenWindowCenter", "screenWindowWidth",
+        ]);
+        assert_eq!(attrs[0].1, "chlist");
+        let mut chlist = Reader { bytes: &attrs[0].2, pos: 0 };
+        for (name, kind) in [("B", 1), ("G", 1), ("R", 1), ("Z", 2)] {
+            assert_eq!(chlist.string(), name);
+            assert_eq!(chlist.i32(), kind);
+            assert_eq!(chlist.take(4), &[0, 0, 0, 0]);
+            assert_eq!((chlist.i32(), chlist.i32()), (1, 1));
+        }
+        assert_eq!(chlist.take(1), &[0]);
+        assert_eq!(attrs[1].2, vec![if compression == Compression::None { 0 } else { 1 }]);
+        let window: Vec<u8> = [0i32, 0, 4, 2].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(attrs[2], ("dataWindow".to_string(), "box2i".to_string(), window.clone()));
+        assert_eq!(attrs[3].2, window);
+
+        let offsets: Vec<u64> = (0..3).map(|_| r.u64()).collect();
+        for (y, &offset) in offsets.iter().enumerate() {
+            assert_eq!(offset as usize, r.pos);
+            assert_eq!(r.i32(), y as i32);
+            let len = r.i32() as usize;
+            let data = r.take(len);
+            // three half channels then one float channel per scanline
+            let raw_len = 5 * (3 * 2 + 4);
+            let raw = if len < raw_len { rle_uncompress(data) } else { data.to_vec() };
+            assert_eq!(raw.len(), raw_len);
+
+            let half = |c: usize, x: usize| u16::from_le_bytes([raw[c * 10 + 2 * x], raw[c * 10 + 2 * x + 1]]);
+            for x in 0..5 {
+                let i = y * 5 + x;
+                assert_eq!(half(0, x), if i < 7 { 0x3C00 } else { 0xC000 });
+                assert_eq!(half(1, x), 0x3800);
+                assert_eq!(half(2, x), f32_to_f16(i as f32));
+                let z = &raw[30 + 4 * x..34 + 4 * x];
+                assert_eq!(z, &3f32.to_le_bytes());
+            }
+        }
+        assert_eq!(r.pos, out.len());
+    }
+
+    #[test]
+    fn uncompressed_layout() {
+        check(Compression::None);
+    }
+
+    #[test]
+    fn rle_layout() {
+        check(Compression::Rle);
+    }
+
+    #[test]
+    fn rle_round_trip() {
+        let cases: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![7],
+            vec![1, 2],
+            vec![0; 1000],
+            (0..1000).map(|i| (i * 37 % 256) as u8).collect(),
+            (0..999).map(|i| if i % 50 < 20 { 9 } else { (i % 7) as u8 }).collect(),
+        ];
+        for raw in cases.iter() {
+            assert_eq!(&rle_uncompress(&rle_compress(raw)), raw);
+        }
+        assert!(rle_compress(&[0; 1000]).len() < 40);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(-2.0), 0xC000);
+        assert_eq!(f32_to_f16(0.1), 0x2E66);
+        assert_eq!(f32_to_f16(65504.0), 0x7BFF);
+        assert_eq!(f32_to_f16(65520.0), 0x7C00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7C00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7E00, 0x7E00);
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2f32.powi(-26)), 0x0000);
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::image::Image;
+use crate::vectors::Color3;
+
+/// In-memory image accumulating weighted radiance samples.
+///
+/// Every pixel keeps the weighted sum of its samples, the sum of the weights
+/// and the number of samples. Samples may be added to any pixel from any
+/// thread, the film only needs to be shared by reference.
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<FilmPixel>,
+}
+
+#[derive(Default)]
+struct FilmPixel {
+    r: AtomicF64,
+    g: AtomicF64,
+    b: AtomicF64,
+    weight: AtomicF64,
+    samples: AtomicU32,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        let pixels = (0..width as usize * height as usize).map(|_| FilmPixel::default()).collect();
+        Film { width, height, pixels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Adds one sample to the pixel in column `x` of row `y`, rows counting from the top.
+    pub fn add_sample(&self, x: u32, y: u32, radiance: &Color3, weight: f64) {
+        let px = self.at(x, y);
+        px.r.add(weight * radiance[0]);
+        px.g.add(weight * radiance[1]);
+        px.b.add(weight * radiance[2]);
+        px.weight.add(weight);
+        px.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Weighted sum of all samples of a pixel.
+    pub fn sum(&self, x: u32, y: u32) -> Color3 {
+        let px = self.at(x, y);
+        Color3::new(px.r.get(), px.g.get(), px.b.get())
+    }
+
+    pub fn weight(&self, x: u32, y: u32) -> f64 {
+        self.at(x, y).weight.get()
+    }
+
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.at(x, y).samples.load(Ordering::Relaxed)
+    }
+
+    /// Weighted mean radiance of a pixel, black when it got no samples.
+    pub fn pixel(&self, x: u32, y: u32) -> Color3 {
+        let weight = self.weight(x, y);
+        if weight > 0.0 {
+            self.sum(x, y) / weight
+        } else {
+            Color3::zero()
+        }
+    }
+
+    /// Resolved radiance of the whole film, ready to be handed to an output encoder.
+    pub fn to_image(&self) -> Image {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                pixels.push(self.pixel(x, y));
+            }
+        }
+        Image::new(self.width as usize, self.height as usize, pixels)
+    }
+
+    fn at(&self, x: u32, y: u32) -> &FilmPixel {
+        assert!(x < self.width && y < self.height, "Pixel ({}, {}) is outside of the film", x, y);
+        &self.pixels[y as usize * self.width as usize + x as usize]
+    }
+}
+
+#[derive(Default)]
+struct AtomicF64 {
+    bits: AtomicU64
+}
+
+impl AtomicF64 {
+    fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    fn add(&self, v: f64) {
+        let mut current = self.bits.load(Ordering::Relaxed);
+        loop {
+            let new = (f64::from_bits(current) + v).to_bits();
+            match self.bits.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn pixels_hold_the_weighted_mean() {
+        let film = Film::new(3, 2);
+        film.add_sample(2, 1, &Color3::new(1.0, 2.0, 4.0), 1.0);
+        film.add_sample(2, 1, &Color3::new(4.0, 2.0, 1.0), 3.0);
+
+        assert_eq!(film.sample_count(2, 1), 2);
+        assert_eq!(film.weight(2, 1), 4.0);
+        assert_eq!(film.sum(2, 1), Color3::new(13.0, 8.0, 7.0));
+        assert_eq!(film.pixel(2, 1), Color3::new(3.25, 2.0, 1.75));
+
+        let image = film.to_image();
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.get(2, 1), Color3::new(3.25, 2.0, 1.75));
+        // untouched pixels stay black
+        assert_eq!(film.sample_count(0, 0), 0);
+        assert_eq!(image.get(0, 0), Color3::zero());
+    }
+
+    #[test]
+    fn samples_from_several_threads_all_count() {
+        let film = Film::new(4, 4);
+        // sums of these small binary fractions are exact in any order
+        thread::scope(|s| {
+            for t in 0..8 {
+                let film = &film;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let weight = if (t + i) % 2 == 0 { 0.5 } else { 1.5 };
+                        for y in 0..4 {
+                            for x in 0..4 {
+                                film.add_sample(x, y, &Color3::new(x as f64, y as f64, t as f64), weight);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(film.sample_count(x, y), 8000);
+                assert_eq!(film.weight(x, y), 8000.0);
+                assert_eq!(film.sum(x, y), Color3::new(8000.0 * x as f64, 8000.0 * y as f64, 3.5 * 8000.0));
+                assert_eq!(film.pixel(x, y), Color3::new(x as f64, y as f64, 3.5));
+            }
+        }
+    }
+}
@@ -1,61 +1,92 @@
-use std::rc::Rc;
+use std::f64::consts::PI;
+use std::sync::Arc;
 use std::vec::Vec;
 
-use crate::materials::{Empty, Material};
+use crate::aabb::Aabb;
+use crate::m::clamp;
+use crate::materials::Material;
 use crate::ray::Ray;
-use crate::vectors::{Point3, Vec3};
+use crate::sampler::Sampler;
+use crate::vectors::{Axis, Point3, Transform, Vec3};
 
-#[derive(Clone)]
-pub struct HitRecord {
+/// Intersection of a ray with a surface, borrowing the material of the object that was hit.
+#[derive(Copy, Clone)]
+pub struct HitRecord<'a> {
     pub p: Point3,
     pub normal: Vec3,
-    pub mat: Rc<dyn Material>,
+    pub mat: &'a dyn Material,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub font_face: bool,
 }
 
 
-impl HitRecord {
-    pub fn empty() -> HitRecord {
-        HitRecord { p: Point3::new(0.0, 0.0, 0.0), normal: Vec3::new(0.0, 0.0, 0.0), mat: Rc::new(Empty::get()), t: 0.0, font_face: false }
+impl<'a> HitRecord<'a> {
+    /// Hit at `t` along `r` with zero texture coordinates, the normal is made
+    /// to face the ray through `set_font_face`.
+    pub fn new(r: &Ray, t: f64, outward_normal: Vec3, mat: &'a dyn Material) -> HitRecord<'a> {
+        let mut hit_record = HitRecord { p: r.at(t), normal: outward_normal, mat, t, u: 0.0, v: 0.0, font_face: false };
+        hit_record.set_font_face(r, outward_normal);
+        hit_record
     }
 
     pub fn set_font_face(self: &mut Self, r: &Ray, outward_normal: Vec3) -> () {
         self.font_face = r.direction.dot(outward_normal) < 0.0;
         self.normal = if self.font_face { outward_normal.clone() } else { -outward_normal };
     }
+
+    /// Replaces the normal used for shading, keeping the side chosen by `set_font_face`.
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        self.normal = if self.font_face { outward_normal } else { -outward_normal };
+    }
 }
 
-pub trait Hittable {
-    fn hit(self: &Self, ray: &Ray, t_min: f64, t_max: f64, hit_record: &mut HitRecord) -> bool;
+pub trait Hittable: Send + Sync {
+    /// Closest intersection with `t` in `[t_min, t_max]`.
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    /// Box enclosing the whole object, `None` for unbounded objects.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Solid angle density, as seen from `origin`, with which `random` picks
+    /// `direction`. Zero for objects that can not be sampled as lights.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Direction from `origin` towards a random point of the object.
+    fn random(&self, _origin: &Point3, _rng: &mut Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
 
 pub struct Sphere {
     cen: Point3,
     r: f64,
-    m: Rc<dyn Material>,
+    m: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new(c: &Point3, r: f64, m: Rc<dyn Material>) -> Sphere {
+    pub fn new(c: &Point3, r: f64, m: Arc<dyn Material>) -> Sphere {
         Sphere { cen: c.clone(), r, m }
     }
 
-    pub fn new_f(x: f64, y: f64, z: f64, r: f64, m: Rc<dyn Material>) -> Sphere {
+    pub fn new_f(x: f64, y: f64, z: f64, r: f64, m: Arc<dyn Material>) -> Sphere {
         Sphere { cen: Vec3::new(x, y, z), r, m }
     }
 }
 
 impl Hittable for Sphere {
-    fn hit(self: &Sphere, r: &Ray, t_min: f64, t_max: f64, hit_record: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.cen;
         let a = r.direction.length_squared();
         let half_b = oc.dot(r.direction);
         let c = oc.length_squared() - self.r * self.r;
         let discriminant = half_b * half_b - a * c;
         if discriminant < 0.0 {
-            return false;
+            return None;
         }
         let sqrtd = discriminant.sqrt();
 
@@ -63,21 +94,407 @@ impl Hittable for Sphere {
         if root < t_min || root > t_max {
             root = (-half_b + sqrtd) / a;
             if root < t_min || root > t_max {
-                return false;
+                return None;
             }
         }
 
-        hit_record.t = root;
-        hit_record.p = r.at(root);
-        let outward_normal = (hit_record.p - self.cen) / self.r;
-        hit_record.set_font_face(r, outward_normal);
-        hit_record.mat = self.m.clone();
-        true
+        let outward_normal = (r.at(root) - self.cen) / self.r;
+        let mut hit_record = HitRecord::new(r, root, outward_normal, self.m.as_ref());
+        let (u, v) = sphere_uv(&outward_normal);
+        hit_record.u = u;
+        hit_record.v = v;
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.r.abs(), self.r.abs(), self.r.abs());
+        Some(Aabb::new(self.cen - r, self.cen + r))
+    }
+
+    // uniform over the cone of directions covered by the sphere,
+    // or over all directions when the origin is inside of it
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.hit(&Ray::new(origin, direction), 0.001, f64::MAX).is_none() {
+            return 0.0;
+        }
+        let dist_sq = (self.cen - *origin).length_squared();
+        let r_sq = self.r * self.r;
+        if dist_sq <= r_sq {
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_max = (1.0 - r_sq / dist_sq).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_max))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let to_center = self.cen - *origin;
+        let dist_sq = to_center.length_squared();
+        let r_sq = self.r * self.r;
+        if dist_sq <= r_sq {
+            return Vec3::random_unit_vector(rng);
+        }
+        let cos_max = (1.0 - r_sq / dist_sq).sqrt();
+        let z = 1.0 + rng.rand_f() * (cos_max - 1.0);
+        let phi = 2.0 * PI * rng.rand_f();
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+
+        let w = to_center.unit_vec();
+        let (u, v) = orthonormal_basis(w);
+        phi.cos() * sin_theta * u + phi.sin() * sin_theta * v + z * w
+    }
+}
+
+// two unit vectors completing `w` to a right handed orthonormal basis
+fn orthonormal_basis(w: Vec3) -> (Vec3, Vec3) {
+    let a = if w[Axis::X].abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let v = w.cross(a).unit_vec();
+    (w.cross(v), v)
+}
+
+/// Texture coordinates of a point on the unit sphere: `u` goes around the Y axis
+/// starting from -X, `v` goes from the bottom pole to the top one.
+pub fn sphere_uv(p: &Point3) -> (f64, f64) {
+    let theta = clamp(-p[Axis::Y], -1.0, 1.0).acos();
+    let phi = (-p[Axis::Z]).atan2(p[Axis::X]) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+/// Single triangle with optional per-vertex shading normals and texture coordinates.
+///
+/// Vertices are expected in counter-clockwise order when looking at the front face.
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: [(f64, f64); 3],
+    m: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(a: &Point3, b: &Point3, c: &Point3, m: Arc<dyn Material>) -> Triangle {
+        Triangle { vertices: [*a, *b, *c], normals: None, uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], m }
+    }
+
+    pub fn with_attributes(vertices: [Point3; 3], normals: Option<[Vec3; 3]>, uvs: Option<[(f64, f64); 3]>, m: Arc<dyn Material>) -> Triangle {
+        Triangle { vertices, normals, uvs: uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]), m }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_triangle(r, t_min, t_max, &self.vertices, self.normals.as_ref(), &self.uvs, self.m.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(padded_box(&self.vertices))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        triangle_pdf_value(&self.vertices, origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        triangle_random(&self.vertices, origin, rng)
+    }
+}
+
+/// Hit record for a triangle shared by stand-alone and mesh triangles.
+pub(crate) fn hit_triangle<'a>(r: &Ray, t_min: f64, t_max: f64, p: &[Point3; 3], normals: Option<&[Vec3; 3]>,
+                               uvs: &[(f64, f64); 3], mat: &'a dyn Material) -> Option<HitRecord<'a>> {
+    let (t, b1, b2) = intersect_triangle(r, t_min, t_max, p)?;
+    let b0 = 1.0 - b1 - b2;
+
+    let outward_normal = (p[1] - p[0]).cross(p[2] - p[0]).unit_vec();
+    let mut hit_record = HitRecord::new(r, t, outward_normal, mat);
+    hit_record.u = b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0;
+    hit_record.v = b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1;
+    if let Some(n) = normals {
+        let mut shading = (b0 * n[0] + b1 * n[1] + b2 * n[2]).unit_vec();
+        if shading.dot(outward_normal) < 0.0 {
+            shading = -shading;
+        }
+        hit_record.set_shading_normal(shading);
+    }
+    Some(hit_record)
+}
+
+// Möller–Trumbore, gives the distance and the barycentric coordinates of the second and third vertex
+fn intersect_triangle(r: &Ray, t_min: f64, t_max: f64, p: &[Point3; 3]) -> Option<(f64, f64, f64)> {
+    let e1 = p[1] - p[0];
+    let e2 = p[2] - p[0];
+    let pvec = r.direction.cross(e2);
+    let det = e1.dot(pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin - p[0];
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(e1);
+    let b2 = r.direction.dot(qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = e2.dot(qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Solid angle density of picking `direction` from `origin` when points are
+/// drawn uniformly over the area of the triangle.
+pub(crate) fn triangle_pdf_value(p: &[Point3; 3], origin: &Point3, direction: &Vec3) -> f64 {
+    let t = match intersect_triangle(&Ray::new(origin, direction), 0.001, f64::MAX, p) {
+        Some((t, _, _)) => t,
+        None => return 0.0,
+    };
+    let n = (p[1] - p[0]).cross(p[2] - p[0]);
+    let area = 0.5 * n.length();
+    let cosine = direction.dot(n).abs() / (direction.length() * n.length());
+    if cosine < 1e-12 {
+        return 0.0;
+    }
+    let dist_sq = t * t * direction.length_squared();
+    dist_sq / (cosine * area)
+}
+
+/// Direction from `origin` to a point drawn uniformly over the triangle.
+pub(crate) fn triangle_random(p: &[Point3; 3], origin: &Point3, rng: &mut Sampler) -> Vec3 {
+    let (mut s, mut t) = (rng.rand_f(), rng.rand_f());
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    p[0] + s * (p[1] - p[0]) + t * (p[2] - p[0]) - *origin
+}
+
+// box around the corners of a flat primitive, padded so that one lying in an
+// axis plane still has some thickness for the slab test
+pub(crate) fn padded_box(points: &[Point3]) -> Aabb {
+    let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+    let bbox = points.iter().fold(Aabb::from_points(points[0], points[0]), |acc, p| Aabb::surrounding(&acc, &Aabb::from_points(*p, *p)));
+    Aabb::new(bbox.min - pad, bbox.max + pad)
+}
+
+const DEGENERATE_AREA_SQUARED: f64 = 1e-20;
+
+/// Parallelogram spanned by the edges `u` and `v` from the corner `q`.
+///
+/// The front face is the one `u x v` points to, texture coordinates run from
+/// 0 to 1 along `u` and `v`. A quad whose edges are parallel or zero is never hit.
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // `normal / |u x v|`, turns a point of the plane into its coordinates along the edges
+    w: Vec3,
+    d: f64,
+    area: f64,
+    m: Arc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(q: &Point3, u: &Vec3, v: &Vec3, m: Arc<dyn Material>) -> Quad {
+        let n = u.cross(*v);
+        if n.length_squared() < DEGENERATE_AREA_SQUARED {
+            // a zero normal makes every ray parallel to the plane
+            let zero = Vec3::zero();
+            return Quad { q: *q, u: *u, v: *v, normal: zero, w: zero, d: 0.0, area: 0.0, m };
+        }
+        let normal = n.unit_vec();
+        Quad { q: *q, u: *u, v: *v, normal, w: n / n.length_squared(), d: normal.dot(*q), area: n.length(), m }
+    }
+
+    /// Rectangle `[x0, x1] x [y0, y1]` in the plane `z = k`, facing +Z.
+    pub fn xy_rect(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, m: Arc<dyn Material>) -> Quad {
+        Quad::new(&Point3::new(x0, y0, k), &Vec3::new(x1 - x0, 0.0, 0.0), &Vec3::new(0.0, y1 - y0, 0.0), m)
+    }
+
+    /// Rectangle `[x0, x1] x [z0, z1]` in the plane `y = k`, facing +Y; `u`
+    /// runs along Z and `v` along X.
+    pub fn xz_rect(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, m: Arc<dyn Material>) -> Quad {
+        Quad::new(&Point3::new(x0, k, z0), &Vec3::new(0.0, 0.0, z1 - z0), &Vec3::new(x1 - x0, 0.0, 0.0), m)
+    }
+
+    /// Rectangle `[y0, y1] x [z0, z1]` in the plane `x = k`, facing +X.
+    pub fn yz_rect(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, m: Arc<dyn Material>) -> Quad {
+        Quad::new(&Point3::new(k, y0, z0), &Vec3::new(0.0, y1 - y0, 0.0), &Vec3::new(0.0, 0.0, z1 - z0), m)
+    }
+
+    // distance and edge coordinates of the intersection with the parallelogram
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let denom = self.normal.dot(r.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(r.origin)) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let planar = r.at(t) - self.q;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some((t, alpha, beta))
     }
 }
 
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, alpha, beta) = self.intersect(r, t_min, t_max)?;
+        let mut hit_record = HitRecord::new(r, t, self.normal, self.m.as_ref());
+        hit_record.u = alpha;
+        hit_record.v = beta;
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(padded_box(&[self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v]))
+    }
+
+    // uniform over the area, converted to solid angle
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let t = match self.intersect(&Ray::new(origin, direction), 0.001, f64::MAX) {
+            Some((t, _, _)) => t,
+            None => return 0.0,
+        };
+        let cosine = direction.dot(self.normal).abs() / direction.length();
+        if cosine < 1e-12 {
+            return 0.0;
+        }
+        let dist_sq = t * t * direction.length_squared();
+        dist_sq / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        self.q + rng.rand_f() * self.u + rng.rand_f() * self.v - *origin
+    }
+}
+
+/// Closed box made of six quads with their front faces pointing outwards.
+///
+/// Named so it does not shadow `std::boxed::Box`.
+pub struct Cuboid {
+    sides: HittableList,
+}
+
+impl Cuboid {
+    /// Axis-aligned box with the opposite corners `a` and `b`.
+    pub fn new(a: &Point3, b: &Point3, m: Arc<dyn Material>) -> Cuboid {
+        let bbox = Aabb::from_points(*a, *b);
+        let d = bbox.max - bbox.min;
+        Cuboid::spanned(&bbox.min, &Vec3::new(d[0], 0.0, 0.0), &Vec3::new(0.0, d[1], 0.0), &Vec3::new(0.0, 0.0, d[2]), m)
+    }
+
+    /// Parallelepiped spanned by the edges `a`, `b` and `c` from the corner `origin`.
+    pub fn spanned(origin: &Point3, a: &Vec3, b: &Vec3, c: &Vec3, m: Arc<dyn Material>) -> Cuboid {
+        // start from the opposite end of `a` when the edges are left handed,
+        // so that the front faces still point outwards
+        let (o, a) = if a.dot(b.cross(*c)) < 0.0 { (*origin + *a, -*a) } else { (*origin, *a) };
+        let (b, c) = (*b, *c);
+
+        let mut sides = HittableList::empty();
+        sides.add(Arc::new(Quad::new(&(o + c), &a, &b, m.clone())));
+        sides.add(Arc::new(Quad::new(&(o + a), &-a, &b, m.clone())));
+        sides.add(Arc::new(Quad::new(&(o + a + c), &-c, &b, m.clone())));
+        sides.add(Arc::new(Quad::new(&o, &c, &b, m.clone())));
+        sides.add(Arc::new(Quad::new(&(o + b + c), &a, &-c, m.clone())));
+        sides.add(Arc::new(Quad::new(&o, &a, &c, m)));
+        Cuboid { sides }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.sides.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.sides.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        self.sides.random(origin, rng)
+    }
+}
+
+/// Instance of another object placed by an affine transform, the object itself is shared.
+///
+/// Rays are taken into object space without normalizing their direction, so
+/// the hit distance is the same in both spaces.
+pub struct Transformed {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    bbox: Option<Aabb>,
+}
+
+impl Transformed {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Transformed {
+        let bbox = object.bounding_box().map(|b| {
+            let corners: Vec<Point3> = (0..8)
+                .map(|i| Vec3::new(if i & 1 == 0 { b.min[0] } else { b.max[0] },
+                                   if i & 2 == 0 { b.min[1] } else { b.max[1] },
+                                   if i & 4 == 0 { b.min[2] } else { b.max[2] }))
+                .map(|c| transform.point(&c))
+                .collect();
+            corners.iter().fold(Aabb::from_points(corners[0], corners[0]), |acc, c| Aabb::surrounding(&acc, &Aabb::from_points(*c, *c)))
+        });
+        Transformed { object, transform, bbox }
+    }
+
+    fn to_object(&self, r: &Ray) -> Ray {
+        let inv = self.transform.inverse();
+        Ray::new(&inv.point(&r.origin), &inv.vector(&r.direction))
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut hit_record = self.object.hit(&self.to_object(r), t_min, t_max)?;
+        let outward_normal = if hit_record.font_face { hit_record.normal } else { -hit_record.normal };
+        hit_record.p = self.transform.point(&hit_record.p);
+        // the inverse transpose keeps the sign of `direction . normal`, so the
+        // side found in object space is found again here
+        hit_record.set_font_face(r, self.transform.normal(&outward_normal).unit_vec());
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    // directions are mapped by the linear part `A`, which changes solid angle
+    // densities by `|A w|^3 / |det A|` for a unit object space direction `w`
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let inv = self.transform.inverse();
+        let local = inv.vector(direction).unit_vec();
+        let pdf = self.object.pdf_value(&inv.point(origin), &local);
+        if pdf <= 0.0 {
+            return 0.0;
+        }
+        pdf * self.transform.vector(&local).length().powi(3) / self.transform.matrix().determinant3().abs()
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let local_origin = self.transform.inverse().point(origin);
+        self.transform.vector(&self.object.random(&local_origin, rng))
+    }
+}
+
+#[derive(Clone)]
 pub struct HittableList {
-    elements: Vec<Rc<dyn Hittable>>
+    elements: Vec<Arc<dyn Hittable>>
 }
 
 impl HittableList {
@@ -85,36 +502,161 @@ impl HittableList {
         HittableList { elements: Vec::new() }
     }
 
-    pub fn add(self: &mut Self, h: Rc<dyn Hittable>) -> () {
+    pub fn add(self: &mut Self, h: Arc<dyn Hittable>) -> () {
         self.elements.push(h);
     }
 
     pub fn clear(self: &mut Self) -> () {
         self.elements.clear();
     }
+
+    pub fn elements(&self) -> &[Arc<dyn Hittable>] {
+        &self.elements
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(self: &Self, ray: &Ray, t_min: f64, t_max: f64, hit_record: &mut HitRecord) -> bool {
-        let mut hit_smth = false;
-        let mut tmp_hit = HitRecord::empty();
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = None;
         let mut closest_so_far = t_max;
 
-        let iter = self.elements.iter();
+        for element in self.elements.iter() {
+            if let Some(hit) = element.hit(ray, t_min, closest_so_far) {
+                closest_so_far = hit.t;
+                closest = Some(hit);
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut result: Option<Aabb> = None;
+        for element in self.elements.iter() {
+            let bbox = element.bounding_box()?;
+            result = Some(match result {
+                Some(acc) => Aabb::surrounding(&acc, &bbox),
+                None => bbox,
+            });
+        }
+        result
+    }
 
-        for element in iter {
-            if element.hit(ray, t_min, closest_so_far, &mut tmp_hit) {
-                hit_smth = true;
-                closest_so_far = tmp_hit.t;
+    // every element is picked with the same probability
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.elements.is_empty() {
+            return 0.0;
+        }
+        let weight = 1.0 / self.elements.len() as f64;
+        self.elements.iter().map(|e| weight * e.pdf_value(origin, direction)).sum()
+    }
 
-                hit_record.normal = tmp_hit.normal;
-                hit_record.p = tmp_hit.p;
-                hit_record.font_face = tmp_hit.font_face;
-                hit_record.t = tmp_hit.t;
-                hit_record.mat.clone_from(&tmp_hit.mat);
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        if self.elements.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let n = self.elements.len();
+        let idx = ((rng.rand_f() * n as f64) as usize).min(n - 1);
+        self.elements[idx].random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_f(0.5, 0.5, 0.5))
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    // hits `object` with a ray arriving at `target` from `target + offset`
+    fn hit_from(object: &dyn Hittable, target: Point3, offset: Vec3) -> Option<(Vec3, bool, f64, f64)> {
+        let r = Ray::new(&(target + offset), &-offset);
+        object.hit(&r, 1e-6, f64::INFINITY).map(|h| {
+            assert!(close(h.p, target), "hit at {:?}, expected {:?}", h.p, target);
+            (h.normal, h.font_face, h.u, h.v)
+        })
+    }
+
+    #[test]
+    fn rects_face_their_axis_and_map_uvs_along_the_edges() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let cases = [
+            (Quad::xy_rect(1.0, 3.0, 2.0, 6.0, 5.0, material()), Point3::new(1.5, 5.0, 5.0), z, (0.25, 0.75)),
+            // u runs along Z and v along X
+            (Quad::xz_rect(1.0, 3.0, 2.0, 6.0, 5.0, material()), Point3::new(1.5, 5.0, 5.0), y, (0.75, 0.25)),
+            (Quad::yz_rect(1.0, 3.0, 2.0, 6.0, 5.0, material()), Point3::new(5.0, 1.5, 5.0), x, (0.25, 0.75)),
+        ];
+        for (quad, p, axis, uv) in cases.iter() {
+            let (normal, front, u, v) = hit_from(quad, *p, 2.0 * *axis).unwrap();
+            assert!(close(normal, *axis) && front);
+            assert!((u - uv.0).abs() < 1e-9 && (v - uv.1).abs() < 1e-9, "{} {}", u, v);
+
+            let (normal, front, _, _) = hit_from(quad, *p, -2.0 * *axis).unwrap();
+            assert!(close(normal, -*axis) && !front);
+        }
+    }
+
+    #[test]
+    fn skewed_quad_uses_edge_coordinates() {
+        let quad = Quad::new(&Point3::zero(), &Vec3::new(2.0, 0.0, 0.0), &Vec3::new(1.0, 1.0, 0.0), material());
+        let (_, _, u, v) = hit_from(&quad, Point3::new(1.5, 0.5, 0.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!((u - 0.5).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+        assert!(hit_from(&quad, Point3::new(0.2, 0.5, 0.0), Vec3::new(0.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn degenerate_quads_are_never_hit() {
+        let edge = Vec3::new(1.0, 0.0, 0.0);
+        let quads = [
+            Quad::new(&Point3::zero(), &edge, &(3.0 * edge), material()),
+            Quad::new(&Point3::zero(), &edge, &Vec3::zero(), material()),
+        ];
+        for quad in quads.iter() {
+            for dir in [Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, -1.0, 0.0), Vec3::new(-1.0, -1.0, -1.0)] {
+                let r = Ray::new(&(Point3::new(0.5, 0.0, 0.0) - 2.0 * dir), &dir);
+                assert!(quad.hit(&r, 1e-6, f64::INFINITY).is_none());
+                assert_eq!(quad.pdf_value(&r.origin, &dir), 0.0);
             }
+            assert!(quad.bounding_box().is_some());
         }
+    }
 
-        return hit_smth;
+    #[test]
+    fn cuboid_normals_point_outwards() {
+        let centre = Point3::new(1.5, 2.0, 2.5);
+        let boxes = [
+            Cuboid::new(&Point3::new(2.0, 3.0, 4.0), &Point3::new(1.0, 1.0, 1.0), material()),
+            // left handed edges
+            Cuboid::spanned(&Point3::new(1.0, 1.0, 1.0), &Vec3::new(0.0, 0.0, 3.0), &Vec3::new(0.0, 2.0, 0.0),
+                            &Vec3::new(1.0, 0.0, 0.0), material()),
+        ];
+        let half = Vec3::new(0.5, 1.0, 1.5);
+        for cuboid in boxes.iter() {
+            for axis in 0..3 {
+                for sign in [1.0, -1.0] {
+                    let n = sign * Vec3::new((axis == 0) as u8 as f64, (axis == 1) as u8 as f64, (axis == 2) as u8 as f64);
+                    let face = centre + Vec3::new(n[0] * half[0], n[1] * half[1], n[2] * half[2]);
+                    let (normal, front, u, v) = hit_from(cuboid, face, 3.0 * n).unwrap();
+                    assert!(close(normal, n) && front, "{:?} {:?}", n, normal);
+                    assert!((u - 0.5).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+
+                    // seen from inside every wall is a back face
+                    let r = Ray::new(&centre, &n);
+                    let h = cuboid.hit(&r, 1e-6, f64::INFINITY).unwrap();
+                    assert!(!h.font_face && close(h.normal, -n));
+                }
+            }
+            let bbox = cuboid.bounding_box().unwrap();
+            assert!(close(bbox.min, Point3::new(1.0, 1.0, 1.0) - Vec3::new(1e-4, 1e-4, 1e-4)));
+            assert!(close(bbox.max, Point3::new(2.0, 3.0, 4.0) + Vec3::new(1e-4, 1e-4, 1e-4)));
+        }
     }
-}
\ No newline at end of file
+}
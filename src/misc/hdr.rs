@@ -0,0 +1,195 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::image::Image;
+use crate::vectors::Color3;
+
+// largest width or height accepted, well beyond any real panorama
+const MAX_SIDE: usize = 1 << 16;
+
+/// Reads a Radiance `.hdr` (RGBE) picture from `path`.
+pub fn load_hdr<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    read_hdr(&mut BufReader::new(File::open(path)?))
+}
+
+/// Reads a Radiance RGBE picture in the usual `-Y height +X width` orientation,
+/// both flat and run-length encoded scanlines are supported.
+pub fn read_hdr<R: BufRead>(r: &mut R) -> io::Result<Image> {
+    let magic = read_line(r)?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("not a Radiance picture"));
+    }
+
+    loop {
+        let line = read_line(r)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid(&format!("unsupported pixel format {}", format)));
+            }
+        }
+    }
+
+    let resolution = read_line(r)?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    if parts.len() != 4 || parts[0] != "-Y" || parts[2] != "+X" {
+        return Err(invalid(&format!("unsupported resolution line {}", resolution)));
+    }
+    let height: usize = parts[1].parse().map_err(|_| invalid("bad image height"))?;
+    let width: usize = parts[3].parse().map_err(|_| invalid("bad image width"))?;
+    if width == 0 || height == 0 {
+        return Err(invalid("empty image"));
+    }
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Err(invalid(&format!("image too large ({} x {})", width, height)));
+    }
+
+    // grows with the data actually read, so a lying header cannot reserve gigabytes up front
+    let mut pixels = Vec::new();
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(r, &mut scanline)?;
+        pixels.extend(scanline.iter().map(rgbe_to_color));
+    }
+
+    Ok(Image::new(width, height, pixels))
+}
+
+fn read_scanline<R: Read>(r: &mut R, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut head = [0u8; 4];
+    r.read_exact(&mut head)?;
+
+    let is_rle = (8..0x8000).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0;
+    if !is_rle {
+        scanline[0] = head;
+        for px in scanline.iter_mut().skip(1) {
+            r.read_exact(px)?;
+        }
+        return Ok(());
+    }
+
+    if ((head[2] as usize) << 8 | head[3] as usize) != width {
+        return Err(invalid("wrong scanline width"));
+    }
+
+    // every component is stored as its own run-length encoded plane
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            r.read_exact(&mut count)?;
+            if count[0] > 128 {
+                let run = (count[0] - 128) as usize;
+                if x + run > width {
+                    return Err(invalid("scanline run overflows the image width"));
+                }
+                let mut value = [0u8; 1];
+                r.read_exact(&mut value)?;
+                for px in scanline[x..x + run].iter_mut() {
+                    px[c] = value[0];
+                }
+                x += run;
+            } else {
+                let run = count[0] as usize;
+                if run == 0 || x + run > width {
+                    return Err(invalid("bad scanline literal run"));
+                }
+                for px in scanline[x..x + run].iter_mut() {
+                    let mut value = [0u8; 1];
+                    r.read_exact(&mut value)?;
+                    px[c] = value[0];
+                }
+                x += run;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color3 {
+    if rgbe[3] == 0 {
+        return Color3::zero();
+    }
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    Color3::new(rgbe[0] as f64 * f, rgbe[1] as f64 * f, rgbe[2] as f64 * f)
+}
+
+fn read_line<R: BufRead>(r: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    r.read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated header"));
+    }
+    line.pop();
+    String::from_utf8(line).map_err(|_| invalid("header is not valid text"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picture(width: usize, height: usize, data: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn reads_run_length_encoded_scanline() {
+        let data = [
+            2, 2, 0, 8,
+            136, 128,                            // R: run of 8
+            8, 0, 16, 32, 48, 64, 80, 96, 112,   // G: 8 literals
+            132, 0, 132, 64,                     // B: two runs of 4
+            136, 129,                            // E: run of 8
+        ];
+        let image = read_hdr(&mut &picture(8, 1, &data)[..]).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 1));
+        // exponent 129 scales the mantissas by 2^-7
+        assert_eq!(image.get(0, 0), Color3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get(3, 0), Color3::new(1.0, 0.375, 0.0));
+        assert_eq!(image.get(7, 0), Color3::new(1.0, 0.875, 0.5));
+    }
+
+    #[test]
+    fn reads_flat_scanlines() {
+        let data = [128, 64, 32, 129, 0, 0, 0, 0];
+        let image = read_hdr(&mut &picture(1, 2, &data)[..]).unwrap();
+        assert_eq!(image.get(0, 0), Color3::new(1.0, 0.5, 0.25));
+        assert_eq!(image.get(0, 1), Color3::zero());
+    }
+
+    #[test]
+    fn rejects_runs_past_the_scanline() {
+        let data = [2, 2, 0, 8, 137, 1];
+        let err = read_hdr(&mut &picture(8, 1, &data)[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        for (w, h) in [(0, 4), (4, 0)] {
+            let err = read_hdr(&mut &picture(w, h, &[])[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_headers_without_allocating() {
+        for (w, h) in [(usize::MAX, 2), (MAX_SIDE + 1, 1), (1, usize::MAX / 3)] {
+            let err = read_hdr(&mut &picture(w, h, &[])[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // a large but legal header without data just runs out of input
+        let err = read_hdr(&mut &picture(MAX_SIDE, MAX_SIDE, &[0; 8])[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::vectors::Color3;
+
+/// Linear RGB image stored row by row starting from the top.
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color3>) -> Image {
+        assert_eq!(width * height, pixels.len(), "Image size does not match the number of pixels");
+        Image { width, height, pixels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color3] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color3 {
+        self.pixels[y * self.width + x]
+    }
+}
@@ -1,12 +1,48 @@
+use std::f64::consts::PI;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use crate::geometry::HitRecord;
-use crate::m::rand_f;
 use crate::ray::Ray;
-use crate::vectors::{Color3, Colors, Vec3};
+use crate::sampler::Sampler;
+use crate::texture::{SolidColor, Texture};
+use crate::vectors::{Color3, Vec3};
 
-pub trait Material {
-    fn scatter(self: &Self, ray_in: &Ray, hit_record: &HitRecord, attenuation: &mut Color3, scattered: &mut Ray) -> bool;
+/// Result of a ray bouncing off a material.
+#[derive(Debug, Copy, Clone)]
+pub struct ScatterRecord {
+    pub attenuation: Color3,
+    pub scattered: Ray,
+    /// Density with which the direction of `scattered` was picked, zero for specular lobes.
+    pub pdf: f64,
+    /// `scattered` is the only direction light can arrive from, so it has to
+    /// be followed and can not be combined with light sampling.
+    pub is_specular: bool,
+}
+
+pub trait Material: Send + Sync {
+    /// Picks the direction the incoming light is carried on to, `None` when the
+    /// material absorbs everything. A sampled direction the surface can not send
+    /// light along, such as one below it, comes back with zero attenuation so
+    /// that it still counts towards `pdf`.
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, rng: &mut Sampler) -> Option<ScatterRecord>;
+
+    /// Solid angle density with which `scatter` picks `direction`. Zero for
+    /// perfectly specular materials, which can not be sampled towards lights.
+    fn pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Cosine weighted BRDF for light arriving from `direction`, so that
+    /// `eval / pdf` is the attenuation `scatter` gives for that direction.
+    fn eval(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vec3) -> Color3 {
+        Color3::zero()
+    }
+
+    /// Radiance emitted from the hit point towards the origin of `ray_in`.
+    fn emitted(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Color3 {
+        Color3::zero()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -19,52 +55,65 @@ impl Empty {
 }
 
 impl Material for Empty {
-    fn scatter(self: &Self, _ray_in: &Ray, _hit_record: &HitRecord, _attenuation: &mut Color3, _scattered: &mut Ray) -> bool {
-        false
+    fn scatter(&self, _ray_in: &Ray, _hit_record: &HitRecord, _rng: &mut Sampler) -> Option<ScatterRecord> {
+        None
     }
 }
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Clone)]
 pub struct Lambertian {
-    albedo: Color3
+    albedo: Arc<dyn Texture>
 }
 
 impl Lambertian {
     pub fn new(c: &Color3) -> Lambertian {
-        Lambertian { albedo: c.clone() }
+        Lambertian { albedo: Arc::new(SolidColor::new(c)) }
     }
     pub fn new_f(r: f64, g: f64, b: f64) -> Lambertian {
-        Lambertian { albedo: Vec3::new(r, g, b) }
+        Lambertian { albedo: Arc::new(SolidColor::new_f(r, g, b)) }
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Lambertian {
+        Lambertian { albedo }
     }
 
-    pub fn rand() -> Lambertian {
-        Lambertian { albedo: Color3::random() * Color3::random() }
+    pub fn rand(rng: &mut Sampler) -> Lambertian {
+        Lambertian::new(&(Color3::random(rng) * Color3::random(rng)))
     }
 }
 
 
 impl Material for Lambertian {
-    fn scatter(self: &Self, _ray_in: &Ray, hit_record: &HitRecord, attenuation: &mut Color3, scattered: &mut Ray) -> bool {
-        let mut scatter_direction = hit_record.normal + Vec3::random_unit_vector();
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, rng: &mut Sampler) -> Option<ScatterRecord> {
+        let mut scatter_direction = hit_record.normal + Vec3::random_unit_vector(rng);
 
         if scatter_direction.near_zero() {
             scatter_direction = hit_record.normal;
         }
 
-        scattered.origin = hit_record.p;
-        scattered.direction = scatter_direction;
-        attenuation[Colors::R] = self.albedo[Colors::R];
-        attenuation[Colors::G] = self.albedo[Colors::G];
-        attenuation[Colors::B] = self.albedo[Colors::B];
-        true
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(hit_record.u, hit_record.v, &hit_record.p),
+            scattered: Ray::new(&hit_record.p, &scatter_direction),
+            pdf: self.pdf(ray_in, hit_record, &scatter_direction),
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, _ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        let cosine = hit_record.normal.dot(direction.unit_vec());
+        if cosine < 0.0 { 0.0 } else { cosine / PI }
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color3 {
+        self.albedo.value(hit_record.u, hit_record.v, &hit_record.p) * self.pdf(ray_in, hit_record, direction)
     }
 }
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Clone)]
 pub struct Metal {
-    albedo: Color3,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
@@ -75,7 +124,7 @@ impl Metal {
         } else {
             fuzz
         };
-        Metal { albedo: albedo.clone(), fuzz: f }
+        Metal { albedo: Arc::new(SolidColor::new(albedo)), fuzz: f }
     }
 
     pub fn new_f(r: f64, g: f64, b: f64, fuzz: f64) -> Metal {
@@ -84,24 +133,69 @@ impl Metal {
         } else {
             fuzz
         };
-        Metal { albedo: Vec3::new(r, g, b), fuzz: f }
+        Metal { albedo: Arc::new(SolidColor::new_f(r, g, b)), fuzz: f }
     }
 
-    pub fn rand() -> Metal {
-        Metal::new(&(Color3::random() * Color3::random()), rand_f())
+    pub fn textured(albedo: Arc<dyn Texture>, fuzz: f64) -> Metal {
+        Metal { albedo, fuzz: fuzz.min(1.0) }
+    }
+
+    pub fn rand(rng: &mut Sampler) -> Metal {
+        let albedo = Color3::random(rng) * Color3::random(rng);
+        Metal::new(&albedo, rng.rand_f())
     }
 }
 
 impl Material for Metal {
-    fn scatter(self: &Self, ray_in: &Ray, hit_record: &HitRecord, attenuation: &mut Color3, scattered: &mut Ray) -> bool {
+    // directions pushed below the surface come back black instead of `None`,
+    // so that integrators mixing in other directions still see the lobe
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, rng: &mut Sampler) -> Option<ScatterRecord> {
+        let reflected = Vec3::reflect(ray_in.direction.unit_vec(), hit_record.normal);
+        let direction = reflected + self.fuzz * Vec3::rand_in_unit_sphere(rng);
+        let attenuation = if direction.dot(hit_record.normal) > 0.0 {
+            self.albedo.value(hit_record.u, hit_record.v, &hit_record.p)
+        } else {
+            Color3::zero()
+        };
+
+        Some(ScatterRecord {
+            attenuation,
+            scattered: Ray::new(&hit_record.p, &direction),
+            pdf: self.pdf(ray_in, hit_record, &direction),
+            is_specular: self.fuzz <= 0.0,
+        })
+    }
+
+    // `scatter` offsets the mirror direction `r` by a point drawn uniformly from
+    // a ball of radius `fuzz`. A direction `w` is picked by the part of the ray
+    // `t * w` inside that ball, `t` between the roots of
+    // `t^2 - 2 (w.r) t + 1 - fuzz^2`, which integrates to
+    // `(t2^3 - t1^3) / (4 pi fuzz^3)`. Directions below the surface keep their
+    // density, they are the ones `scatter` turns black.
+    fn pdf(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> f64 {
+        if self.fuzz <= 0.0 {
+            return 0.0;
+        }
         let reflected = Vec3::reflect(ray_in.direction.unit_vec(), hit_record.normal);
-        scattered.origin = hit_record.p;
-        scattered.direction = reflected + self.fuzz * Vec3::rand_in_unit_sphere();
-        attenuation[Colors::R] = self.albedo[Colors::R];
-        attenuation[Colors::G] = self.albedo[Colors::G];
-        attenuation[Colors::B] = self.albedo[Colors::B];
+        let c = direction.unit_vec().dot(reflected);
+        let discriminant = c * c - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let root = discriminant.sqrt();
+        let t2 = c + root;
+        let t1 = (c - root).max(0.0);
+        if t2 <= 0.0 {
+            return 0.0;
+        }
+        (t2 - t1) * (t2 * t2 + t2 * t1 + t1 * t1) / (4.0 * PI * self.fuzz.powi(3))
+    }
 
-        return scattered.direction.dot(hit_record.normal) > 0.0;
+    fn eval(&self, ray_in: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Color3 {
+        if direction.dot(hit_record.normal) <= 0.0 {
+            return Color3::zero();
+        }
+        self.albedo.value(hit_record.u, hit_record.v, &hit_record.p) * self.pdf(ray_in, hit_record, direction)
     }
 }
 
@@ -117,11 +211,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(self: &Self, ray_in: &Ray, hit_record: &HitRecord, attenuation: &mut Color3, scattered: &mut Ray) -> bool {
-        attenuation[Colors::R] = 1.0;
-        attenuation[Colors::G] = 1.0;
-        attenuation[Colors::B] = 1.0;
-
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord, rng: &mut Sampler) -> Option<ScatterRecord> {
         let refraction_ratio = if hit_record.font_face {
             1.0 / self.ir
         } else {
@@ -135,15 +225,43 @@ impl Material for Dielectric {
 
         let can_refract = refraction_ratio * sin_theta > 1.0;
 
-        let dir = if can_refract || reflectance(cos_theta, refraction_ratio) > rand_f() {
+        let dir = if can_refract || reflectance(cos_theta, refraction_ratio) > rng.rand_f() {
             Vec3::reflect(unit_direction, hit_record.normal)
         } else {
             Vec3::refract(unit_direction, hit_record.normal, refraction_ratio)
         };
 
-        scattered.origin = hit_record.p;
-        scattered.direction = dir;
-        true
+        Some(ScatterRecord {
+            attenuation: Color3::new(1.0, 1.0, 1.0),
+            scattered: Ray::new(&hit_record.p, &dir),
+            pdf: 0.0,
+            is_specular: true,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DiffuseLight {
+    emit: Color3
+}
+
+impl DiffuseLight {
+    pub fn new(c: &Color3) -> DiffuseLight {
+        DiffuseLight { emit: *c }
+    }
+
+    pub fn new_f(r: f64, g: f64, b: f64) -> DiffuseLight {
+        DiffuseLight { emit: Vec3::new(r, g, b) }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray_in: &Ray, _hit_record: &HitRecord, _rng: &mut Sampler) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Color3 {
+        self.emit
     }
 }
 
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::geometry::{hit_triangle, padded_box, triangle_pdf_value, triangle_random, HitRecord, Hittable, HittableList};
+use crate::materials::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vectors::{Point3, Vec3};
+
+/// Triangles sharing one vertex buffer and one material.
+///
+/// `normals` and `uvs` are either empty or hold one entry per position,
+/// every face refers to its three corners through `indices`.
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    m: Arc<dyn Material>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Point3>,
+               normals: Vec<Vec3>,
+               uvs: Vec<(f64, f64)>,
+               indices: Vec<[usize; 3]>,
+               m: Arc<dyn Material>) -> TriangleMesh {
+        assert!(normals.is_empty() || normals.len() == positions.len(), "Mesh needs one normal per vertex");
+        assert!(uvs.is_empty() || uvs.len() == positions.len(), "Mesh needs one uv per vertex");
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()), "Mesh index out of range");
+        TriangleMesh { positions, normals, uvs, indices, m }
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn face(self: &Arc<Self>, face: usize) -> MeshTriangle {
+        assert!(face < self.indices.len(), "Mesh face out of range");
+        MeshTriangle { mesh: self.clone(), face }
+    }
+
+    /// Every face as a separate hittable, ready to be put into a `BvhNode`.
+    pub fn faces(self: &Arc<Self>) -> HittableList {
+        let mut list = HittableList::empty();
+        for face in 0..self.indices.len() {
+            list.add(Arc::new(self.face(face)));
+        }
+        list
+    }
+
+    fn corners(&self, face: usize) -> [Point3; 3] {
+        let [a, b, c] = self.indices[face];
+        [self.positions[a], self.positions[b], self.positions[c]]
+    }
+}
+
+/// One face of a `TriangleMesh`.
+pub struct MeshTriangle {
+    mesh: Arc<TriangleMesh>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mesh = &self.mesh;
+        let [a, b, c] = mesh.indices[self.face];
+
+        let normals = if mesh.normals.is_empty() {
+            None
+        } else {
+            Some([mesh.normals[a], mesh.normals[b], mesh.normals[c]])
+        };
+        let uvs = if mesh.uvs.is_empty() {
+            [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+        } else {
+            [mesh.uvs[a], mesh.uvs[b], mesh.uvs[c]]
+        };
+
+        hit_triangle(r, t_min, t_max, &mesh.corners(self.face), normals.as_ref(), &uvs, mesh.m.as_ref())
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(padded_box(&self.mesh.corners(self.face)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        triangle_pdf_value(&self.mesh.corners(self.face), origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        triangle_random(&self.mesh.corners(self.face), origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    // 2 x 2 square in the z = 0 plane made of two faces, both facing +Z
+    fn square(normals: Vec<Vec3>, uvs: Vec<(f64, f64)>) -> Arc<TriangleMesh> {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, 0.0, 0.0),
+                             Point3::new(0.0, 2.0, 0.0), Point3::new(2.0, 2.0, 0.0)];
+        Arc::new(TriangleMesh::new(positions, normals, uvs, vec![[0, 1, 2], [1, 3, 2]],
+                                   Arc::new(Lambertian::new_f(0.5, 0.5, 0.5))))
+    }
+
+    #[test]
+    fn interpolates_normals_and_uvs_at_barycentric_point() {
+        let normals = vec![Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0),
+                           Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        let uvs = vec![(0.1, 0.2), (0.9, 0.3), (0.2, 0.8), (1.0, 1.0)];
+        let faces = square(normals, uvs).faces();
+
+        // (0.5, 0.5, 0) has barycentric coordinates (0.5, 0.25, 0.25) in the first face
+        let shading = Vec3::new(0.25, 0.25, 0.5).unit_vec();
+        let above = Ray::new(&Point3::new(0.5, 0.5, 3.0), &Vec3::new(0.0, 0.0, -1.0));
+        let h = faces.hit(&above, 0.001, f64::INFINITY).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-9);
+        assert!(h.font_face);
+        assert!(close(h.normal, shading), "{:?}", h.normal);
+        assert!((h.u - 0.325).abs() < 1e-9 && (h.v - 0.375).abs() < 1e-9, "uv ({}, {})", h.u, h.v);
+
+        // from behind the normal is flipped towards the ray
+        let below = Ray::new(&Point3::new(0.5, 0.5, -1.0), &Vec3::new(0.0, 0.0, 1.0));
+        let h = faces.hit(&below, 0.001, f64::INFINITY).unwrap();
+        assert!(!h.font_face);
+        assert!(close(h.normal, -shading), "{:?}", h.normal);
+        assert!((h.u - 0.325).abs() < 1e-9 && (h.v - 0.375).abs() < 1e-9);
+    }
+
+    #[test]
+    fn faces_without_attributes_use_the_geometric_normal() {
+        let mesh = square(Vec::new(), Vec::new());
+        // (1.5, 1.5, 0) has barycentric coordinates (0.25, 0.5, 0.25) in the second face
+        let r = Ray::new(&Point3::new(1.5, 1.5, 1.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(mesh.face(0).hit(&r, 0.001, f64::INFINITY).is_none());
+        let face = mesh.face(1);
+        let h = face.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(h.font_face);
+        assert!(close(h.normal, Vec3::new(0.0, 0.0, 1.0)));
+        assert!((h.u - 0.5).abs() < 1e-9 && (h.v - 0.25).abs() < 1e-9);
+    }
+}
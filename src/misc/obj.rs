@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::geometry::HittableList;
+use crate::materials::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::TriangleMesh;
+use crate::vectors::{Color3, Point3, Vec3};
+
+/// Problem found while reading an OBJ or MTL file, `line` is 1-based and
+/// zero when the file could not be read at all.
+#[derive(Debug, Clone)]
+pub struct ObjError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for ObjError {}
+
+pub type Materials = HashMap<String, Arc<dyn Material>>;
+
+/// Loads a Wavefront OBJ file, resolving `mtllib` statements relative to its directory.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<HittableList, ObjError> {
+    let path = path.as_ref();
+    let file = open(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_obj(BufReader::new(file), &path.display().to_string(), base_dir)
+}
+
+/// Parses OBJ text into a list of triangles, one mesh per group and material,
+/// polygons with more than three corners are split into fans.
+pub fn parse_obj<R: BufRead>(reader: R, file: &str, base_dir: &Path) -> Result<HittableList, ObjError> {
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut materials: Materials = HashMap::new();
+
+    let default_mat: Arc<dyn Material> = Arc::new(Lambertian::new_f(0.8, 0.8, 0.8));
+    let mut current = MeshBuilder::new(default_mat.clone());
+    let mut meshes: Vec<MeshBuilder> = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |message: String| ObjError { file: file.to_string(), line: line_no, message };
+        let line = line.map_err(|e| err(e.to_string()))?;
+        let mut words = statement(&line);
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+
+        match keyword {
+            "v" => positions.push(parse_position(&args).map_err(err)?),
+            "vn" => normals.push(parse_normal(&args).map_err(err)?),
+            "vt" => {
+                if args.is_empty() || args.len() > 3 {
+                    return Err(err(format!("expected 1 to 3 texture coordinates, got {}", args.len())));
+                }
+                let u = parse_f64(args[0]).map_err(err)?;
+                let v = if args.len() > 1 { parse_f64(args[1]).map_err(err)? } else { 0.0 };
+                uvs.push((u, v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(err(format!("face needs at least 3 vertices, got {}", args.len())));
+                }
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    corners.push(parse_corner(arg, positions.len(), uvs.len(), normals.len()).map_err(err)?);
+                }
+                for i in 1..corners.len() - 1 {
+                    current.add_face([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                let m = current.m.clone();
+                meshes.push(std::mem::replace(&mut current, MeshBuilder::new(m)));
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                let m = match materials.get(&name) {
+                    Some(m) => m.clone(),
+                    None => return Err(err(format!("unknown material {}", name))),
+                };
+                meshes.push(std::mem::replace(&mut current, MeshBuilder::new(m)));
+            }
+            "mtllib" => {
+                for name in args.iter() {
+                    let path = base_dir.join(name);
+                    let mtl = open(&path)
+                        .map_err(|e| err(format!("cannot open {}: {}", path.display(), e.message)))?;
+                    materials.extend(parse_mtl(BufReader::new(mtl), &path.display().to_string())?);
+                }
+            }
+            // smoothing groups, lines, points and free-form geometry are not rendered
+            _ => {}
+        }
+    }
+    meshes.push(current);
+
+    let mut list = HittableList::empty();
+    for mesh in meshes.into_iter().filter(|m| !m.faces.is_empty()) {
+        for face in mesh.build(&positions, &uvs, &normals).faces().elements() {
+            list.add(face.clone());
+        }
+    }
+    Ok(list)
+}
+
+/// Parses an MTL library, mapping every material onto the closest built-in one:
+/// an emissive `Ke` gives `DiffuseLight`, transparency (`d` below one) gives
+/// `Dielectric` with the `Ni` index, a specular `Ks` stronger than `Kd` gives
+/// `Metal` whose fuzz follows `Ns`, everything else is `Lambertian` with `Kd`.
+pub fn parse_mtl<R: BufRead>(reader: R, file: &str) -> Result<Materials, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlParams)> = None;
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |message: String| ObjError { file: file.to_string(), line: line_no, message };
+        let line = line.map_err(|e| err(e.to_string()))?;
+        let mut words = statement(&line);
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, params)) = current.take() {
+                materials.insert(name, params.material());
+            }
+            if args.is_empty() {
+                return Err(err("newmtl without a name".to_string()));
+            }
+            current = Some((args.join(" "), MtlParams::default()));
+            continue;
+        }
+
+        let params = match current.as_mut() {
+            Some((_, params)) => params,
+            None => return Err(err(format!("{} before any newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => params.kd = parse_color(&args).map_err(err)?,
+            "Ks" => params.ks = parse_color(&args).map_err(err)?,
+            "Ke" => params.ke = parse_color(&args).map_err(err)?,
+            "Ni" => params.ni = parse_single(&args).map_err(err)?,
+            "Ns" => params.ns = parse_single(&args).map_err(err)?,
+            "d" => params.d = parse_single(&args).map_err(err)?,
+            "Tr" => params.d = 1.0 - parse_single(&args).map_err(err)?,
+            // ambient color, illumination model and texture maps have no counterpart
+            _ => {}
+        }
+    }
+    if let Some((name, params)) = current.take() {
+        materials.insert(name, params.material());
+    }
+    Ok(materials)
+}
+
+struct MtlParams {
+    kd: Color3,
+    ks: Color3,
+    ke: Color3,
+    ni: f64,
+    ns: f64,
+    d: f64,
+}
+
+impl Default for MtlParams {
+    fn default() -> MtlParams {
+        MtlParams {
+            kd: Color3::new(0.8, 0.8, 0.8),
+            ks: Color3::zero(),
+            ke: Color3::zero(),
+            ni: 1.5,
+            ns: 0.0,
+            d: 1.0,
+        }
+    }
+}
+
+impl MtlParams {
+    fn material(&self) -> Arc<dyn Material> {
+        if self.ke.max_component() > 0.0 {
+            Arc::new(DiffuseLight::new(&self.ke))
+        } else if self.d < 1.0 {
+            Arc::new(Dielectric::new(if self.ni > 0.0 { self.ni } else { 1.5 }))
+        } else if self.ks.max_component() > self.kd.max_component() {
+            // Phong exponent to a roughly matching lobe width
+            let fuzz = (2.0 / (self.ns.max(0.0) + 2.0)).sqrt();
+            Arc::new(Metal::new(&self.ks, fuzz))
+        } else {
+            Arc::new(Lambertian::new(&self.kd))
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+// faces of one group sharing one material, collected before the mesh is built
+struct MeshBuilder {
+    m: Arc<dyn Material>,
+    faces: Vec<[Corner; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(m: Arc<dyn Material>) -> MeshBuilder {
+        MeshBuilder { m, faces: Vec::new() }
+    }
+
+    fn add_face(&mut self, face: [Corner; 3]) {
+        self.faces.push(face);
+    }
+
+    fn build(self, positions: &[Point3], uvs: &[(f64, f64)], normals: &[Vec3]) -> Arc<TriangleMesh> {
+        let corners = self.faces.iter().flatten();
+        let has_uvs = corners.clone().all(|c| c.uv.is_some());
+        let has_normals = corners.clone().all(|c| c.normal.is_some());
+
+        let mut remap: HashMap<Corner, usize> = HashMap::new();
+        let mut mesh_positions = Vec::new();
+        let mut mesh_uvs = Vec::new();
+        let mut mesh_normals = Vec::new();
+        let mut indices = Vec::with_capacity(self.faces.len());
+
+        for face in self.faces.iter() {
+            let mut idx = [0; 3];
+            for (k, corner) in face.iter().enumerate() {
+                let key = Corner {
+                    position: corner.position,
+                    uv: if has_uvs { corner.uv } else { None },
+                    normal: if has_normals { corner.normal } else { None },
+                };
+                idx[k] = *remap.entry(key).or_insert_with(|| {
+                    mesh_positions.push(positions[key.position]);
+                    if let Some(uv) = key.uv {
+                        mesh_uvs.push(uvs[uv]);
+                    }
+                    if let Some(normal) = key.normal {
+                        mesh_normals.push(normals[normal]);
+                    }
+                    mesh_positions.len() - 1
+                });
+            }
+            indices.push(idx);
+        }
+
+        Arc::new(TriangleMesh::new(mesh_positions, mesh_normals, mesh_uvs, indices, self.m))
+    }
+}
+
+// splits a line into words, dropping comments
+fn statement(line: &str) -> std::str::SplitWhitespace<'_> {
+    line.split('#').next().unwrap_or("").split_whitespace()
+}
+
+fn parse_corner(arg: &str, n_positions: usize, n_uvs: usize, n_normals: usize) -> Result<Corner, String> {
+    let mut parts = arg.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), n_positions, "vertex")?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(resolve_index(s, n_uvs, "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(resolve_index(s, n_normals, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("malformed face vertex {}", arg));
+    }
+    Ok(Corner { position, uv, normal })
+}
+
+// OBJ indices are 1-based, negative ones count back from the last element
+fn resolve_index(s: &str, count: usize, what: &str) -> Result<usize, String> {
+    let i: i64 = s.parse().map_err(|_| format!("bad {} index {}", what, s))?;
+    let resolved = if i > 0 { i - 1 } else { count as i64 + i };
+    if i == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{} index {} out of range", what, s));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_f64(s: &str) -> Result<f64, String> {
+    s.parse().map_err(|_| format!("bad number {}", s))
+}
+
+fn parse_single(args: &[&str]) -> Result<f64, String> {
+    if args.len() != 1 {
+        return Err(format!("expected 1 value, got {}", args.len()));
+    }
+    parse_f64(args[0])
+}
+
+// vertex positions may carry an extra w component, which is ignored
+fn parse_position(args: &[&str]) -> Result<Point3, String> {
+    if args.len() < 3 || args.len() > 4 {
+        return Err(format!("expected 3 or 4 values, got {}", args.len()));
+    }
+    parse_triple(args)
+}
+
+fn parse_normal(args: &[&str]) -> Result<Vec3, String> {
+    if args.len() != 3 {
+        return Err(format!("expected 3 values, got {}", args.len()));
+    }
+    parse_triple(args)
+}
+
+// a single value stands for a gray
+fn parse_color(args: &[&str]) -> Result<Color3, String> {
+    match args.len() {
+        1 => {
+            let c = parse_f64(args[0])?;
+            Ok(Color3::new(c, c, c))
+        }
+        3 => parse_triple(args),
+        n => Err(format!("expected 1 or 3 values, got {}", n)),
+    }
+}
+
+fn parse_triple(args: &[&str]) -> Result<Vec3, String> {
+    Ok(Vec3::new(parse_f64(args[0])?, parse_f64(args[1])?, parse_f64(args[2])?))
+}
+
+fn open(path: &Path) -> Result<File, ObjError> {
+    File::open(path).map_err(|e| ObjError { file: path.display().to_string(), line: 0, message: e.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Hittable;
+    use crate::ray::Ray;
+
+    fn parse(text: &str) -> Result<HittableList, ObjError> {
+        parse_obj(text.as_bytes(), "test.obj", Path::new(""))
+    }
+
+    fn hits(list: &HittableList, x: f64, y: f64) -> bool {
+        let r = Ray::new(&Point3::new(x, y, 1.0), &Vec3::new(0.0, 0.0, -1.0));
+        list.hit(&r, 1e-3, f64::INFINITY).is_some()
+    }
+
+    #[test]
+    fn polygons_are_split_into_fans() {
+        let list = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0.5 1.5 0\nv 0 1 0\nf 1 2 3 4 5\n").unwrap();
+        assert_eq!(list.elements().len(), 3);
+        assert!(hits(&list, 0.5, 0.5));
+        assert!(hits(&list, 0.5, 1.2));
+        assert!(!hits(&list, 1.5, 0.5));
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let list = parse("v 5 5 0\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 9 9 0\n").unwrap();
+        assert_eq!(list.elements().len(), 1);
+        assert!(hits(&list, 0.2, 0.2));
+        assert!(!hits(&list, 0.8, 0.8));
+        assert_eq!(resolve_index("-1", 4, "vertex"), Ok(3));
+        assert_eq!(resolve_index("1", 4, "vertex"), Ok(0));
+        assert!(resolve_index("0", 4, "vertex").is_err());
+        assert!(resolve_index("-5", 4, "vertex").is_err());
+    }
+
+    #[test]
+    fn errors_carry_line_numbers() {
+        let cases = [
+            ("v 0 0 0\n\nv 1 x 0\n", 3),
+            ("v 0 0 0\nv 1 0 0\nf 1 2\n", 3),
+            ("# comment\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n", 5),
+            ("vn 0 0 1 1\n", 1),
+            ("v 0 0 0\nusemtl missing\n", 2),
+        ];
+        for (text, line) in cases.iter() {
+            let e = parse(text).err().expect(text);
+            assert_eq!(e.line, *line, "{}", e);
+            assert_eq!(e.file, "test.obj");
+        }
+    }
+
+    #[test]
+    fn mtl_colors_take_one_or_three_values() {
+        let mtl = "newmtl gray\nKd 0.5\nnewmtl red\nKd 1 0 0\n";
+        let materials = parse_mtl(mtl.as_bytes(), "test.mtl").unwrap();
+        assert_eq!(materials.len(), 2);
+
+        let e = parse_mtl("newmtl bad\nKd 1 0\n".as_bytes(), "test.mtl").err().unwrap();
+        assert_eq!(e.line, 2);
+    }
+}
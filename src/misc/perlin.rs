@@ -0,0 +1,156 @@
+use crate::sampler::Sampler;
+use crate::vectors::{Axis, Point3, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient noise on the integer lattice with Hermite smoothed trilinear
+/// interpolation. The lattice gradients and permutations are drawn from the
+/// given `Sampler`, so the same seed gives the same pattern.
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut Sampler) -> Perlin {
+        let ranvec = (0..POINT_COUNT).map(|_| Vec3::random_unit_vector(rng)).collect();
+        let perm_x = generate_perm(rng);
+        let perm_y = generate_perm(rng);
+        let perm_z = generate_perm(rng);
+        Perlin { ranvec, perm_x, perm_y, perm_z }
+    }
+
+    /// Noise value roughly in `[-1, 1]`, zero on every lattice point.
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let fx = p[Axis::X].floor();
+        let fy = p[Axis::Y].floor();
+        let fz = p[Axis::Z].floor();
+        let u = p[Axis::X] - fx;
+        let v = p[Axis::Y] - fy;
+        let w = p[Axis::Z] - fz;
+        let i = fx as i64;
+        let j = fy as i64;
+        let k = fz as i64;
+
+        let mut c = [[[Vec3::zero(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, g) in row.iter_mut().enumerate() {
+                    let idx = self.perm_x[wrap(i + di as i64)]
+                        ^ self.perm_y[wrap(j + dj as i64)]
+                        ^ self.perm_z[wrap(k + dk as i64)];
+                    *g = self.ranvec[idx];
+                }
+            }
+        }
+
+        perlin_interp(&c, u, v, w)
+    }
+
+    /// Sum of `depth` octaves of `|noise|`, each twice the frequency and half the weight of the previous.
+    pub fn turb(&self, p: &Point3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p).abs();
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+
+        accum
+    }
+
+    /// Signed fractal Brownian motion, same octaves as `turb`.
+    pub fn fbm(&self, p: &Point3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+
+        accum
+    }
+}
+
+fn wrap(i: i64) -> usize {
+    (i & (POINT_COUNT as i64 - 1)) as usize
+}
+
+fn generate_perm(rng: &mut Sampler) -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    for i in (1..POINT_COUNT).rev() {
+        let target = ((rng.rand_f() * (i + 1) as f64) as usize).min(i);
+        p.swap(i, target);
+    }
+    p
+}
+
+fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    let uu = u * u * (3.0 - 2.0 * u);
+    let vv = v * v * (3.0 - 2.0 * v);
+    let ww = w * w * (3.0 - 2.0 * w);
+    let mut accum = 0.0;
+
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, g) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight_v = Vec3::new(u - fi, v - fj, w - fk);
+                accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * g.dot(weight_v);
+            }
+        }
+    }
+
+    accum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(n: usize) -> Vec<Point3> {
+        let mut rng = Sampler::new(99);
+        (0..n).map(|_| Point3::new(rng.rand_f_mm(-50.0, 50.0), rng.rand_f_mm(-50.0, 50.0), rng.rand_f_mm(-50.0, 50.0)))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_noise() {
+        let a = Perlin::new(&mut Sampler::new(5));
+        let b = Perlin::new(&mut Sampler::new(5));
+        let c = Perlin::new(&mut Sampler::new(6));
+        let mut differs = false;
+        for p in points(500) {
+            assert_eq!(a.noise(&p).to_bits(), b.noise(&p).to_bits());
+            assert_eq!(a.turb(&p, 7).to_bits(), b.turb(&p, 7).to_bits());
+            assert_eq!(a.fbm(&p, 5).to_bits(), b.fbm(&p, 5).to_bits());
+            differs |= a.noise(&p) != c.noise(&p);
+        }
+        assert!(differs, "different seeds gave the same noise");
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_vanishes_on_the_lattice() {
+        let perlin = Perlin::new(&mut Sampler::new(5));
+        for p in points(10_000) {
+            let n = perlin.noise(&p);
+            assert!((-1.0..=1.0).contains(&n), "noise {} at {:?}", n, p);
+            assert!(perlin.turb(&p, 7) >= 0.0);
+        }
+        for i in -3..3 {
+            let p = Point3::new(i as f64, (2 * i) as f64, 7.0);
+            assert_eq!(perlin.noise(&p), 0.0);
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::io::{self, Write};
+
+use crate::m::clamp;
+use crate::vectors::{Color3, Colors};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+/// Writes an RGB PNG. `pixels` hold display-ready values in `[0, 1]`, row by row
+/// starting from the top, anything outside is clamped. PNG has no empty images,
+/// a zero width or height fails with `InvalidInput`.
+pub fn write_png<W: Write>(w: &mut W, width: u32, height: u32, pixels: &[Color3], depth: BitDepth) -> io::Result<()> {
+    assert_eq!(width as usize * height as usize, pixels.len(), "PNG size does not match the number of pixels");
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "PNG images need at least one pixel"));
+    }
+
+    w.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.push(if depth == BitDepth::Eight { 8 } else { 16 });
+    // truecolor, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[2, 0, 0, 0]);
+    write_chunk(w, b"IHDR", &ihdr)?;
+
+    let raw = filter_rows(width as usize, pixels, depth);
+    write_chunk(w, b"IDAT", &zlib_compress(&raw))?;
+    write_chunk(w, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32_update(crc32_update(0xFFFF_FFFF, kind), data) ^ 0xFFFF_FFFF;
+    w.write_all(&crc.to_be_bytes())
+}
+
+// serializes every row and prefixes it with the filter giving the smallest sum of absolute residuals
+fn filter_rows(width: usize, pixels: &[Color3], depth: BitDepth) -> Vec<u8> {
+    let bpp = if depth == BitDepth::Eight { 3 } else { 6 };
+    let stride = width * bpp;
+    let mut out = Vec::with_capacity((stride + 1) * (pixels.len() / width.max(1)));
+    let mut prev = vec![0u8; stride];
+    let mut row = Vec::with_capacity(stride);
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+
+    for line in pixels.chunks(width.max(1)) {
+        row.clear();
+        for px in line {
+            for c in [px[Colors::R], px[Colors::G], px[Colors::B]] {
+                let v = clamp(c, 0.0, 1.0);
+                if depth == BitDepth::Eight {
+                    row.push((v * 255.0).round() as u8);
+                } else {
+                    row.extend_from_slice(&((v * 65535.0).round() as u16).to_be_bytes());
+                }
+            }
+        }
+
+        let mut best_filter = 0;
+        let mut best_score = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..stride {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = prev[i];
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[i] = row[i].wrapping_sub(predicted);
+            }
+            let score = candidate.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+            if score < best_score {
+                best_score = score;
+                best_filter = filter;
+                best.copy_from_slice(&candidate);
+            }
+        }
+
+        out.push(best_filter);
+        out.extend_from_slice(&best);
+        prev.copy_from_slice(&row);
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// zlib stream holding a single deflate block with the fixed Huffman codes,
+/// repeated byte sequences are found with hash chains over a 32K window.
+fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    out.bytes.extend_from_slice(&[0x78, 0x9C]);
+
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes)
+    out.write_bits(1, 1);
+    out.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash3(&data[pos..]);
+            let mut candidate = head[h];
+            let mut chain = 0;
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(data[pos..pos + max_len].iter()).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        let advance = if best_len >= MIN_MATCH {
+            write_match(&mut out, best_len, best_dist);
+            best_len
+        } else {
+            write_literal(&mut out, data[pos] as u16);
+            1
+        };
+
+        for p in pos..(pos + advance) {
+            if p + MIN_MATCH <= data.len() {
+                let h = hash3(&data[p..]);
+                prev[p % WINDOW_SIZE] = head[h];
+                head[h] = p;
+            }
+        }
+        pos += advance;
+    }
+
+    write_literal(&mut out, 256);
+    out.flush();
+    out.bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    out.bytes
+}
+
+fn hash3(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_literal(out: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xC0 + symbol - 280, 8),
+    };
+    out.write_code(code as u32, len);
+}
+
+fn write_match(out: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.iter().rposition(|&b| b as usize <= len).unwrap();
+    write_literal(out, 257 + li as u16);
+    out.write_bits((len - LENGTH_BASE[li] as usize) as u32, LENGTH_EXTRA[li] as u32);
+
+    let di = DIST_BASE.iter().rposition(|&b| b as usize <= dist).unwrap();
+    out.write_code(di as u32, 5);
+    out.write_bits((dist - DIST_BASE[di] as usize) as u32, DIST_EXTRA[di] as u32);
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    n: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: Vec::new(), acc: 0, n: 0 }
+    }
+
+    // plain values go least significant bit first
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.acc |= (value as u64) << self.n;
+        self.n += count;
+        while self.n >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write_bits(reversed, len);
+    }
+
+    fn flush(&mut self) {
+        if self.n > 0 {
+            self.bytes.push(self.acc as u8);
+            self.acc = 0;
+            self.n = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc32(data: &[u8]) -> u32 {
+        crc32_update(0xFFFF_FFFF, data) ^ 0xFFFF_FFFF
+    }
+
+    #[test]
+    fn checksums_match_known_vectors() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+        // long enough to need the modulo reduction
+        assert_eq!(adler32(&[0xFF; 6000]), 0xA497_59EA);
+    }
+
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut v = 0;
+            for i in 0..count {
+                v |= ((self.bytes[self.pos / 8] >> (self.pos % 8)) as u32 & 1) << i;
+                self.pos += 1;
+            }
+            v
+        }
+
+        fn code(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn literal(&mut self) -> usize {
+            let code = self.code(7);
+            if code <= 23 {
+                return 256 + code as usize;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xBF => code as usize - 0x30,
+                0xC0..=0xC7 => 280 + code as usize - 0xC0,
+                _ => 144 + (code << 1 | self.bits(1)) as usize - 0x190,
+            }
+        }
+    }
+
+    // inflates the single fixed Huffman block written by zlib_compress
+    fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(&stream[..2], &[0x78, 0x9C]);
+        let mut r = BitReader { bytes: &stream[2..], pos: 0 };
+        assert_eq!(r.bits(1), 1);
+        assert_eq!(r.bits(2), 1);
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let symbol = r.literal();
+            if symbol < 256 {
+                out.push(symbol as u8);
+            } else if symbol == 256 {
+                break;
+            } else {
+                let li = symbol - 257;
+                let len = LENGTH_BASE[li] as usize + r.bits(LENGTH_EXTRA[li] as u32) as usize;
+                let di = r.code(5) as usize;
+                let dist = DIST_BASE[di] as usize + r.bits(DIST_EXTRA[di] as u32) as usize;
+                for _ in 0..len {
+                    out.push(out[out.len() - dist]);
+                }
+            }
+        }
+        let end = 2 + r.pos.div_ceil(8);
+        assert_eq!(&stream[end..], &adler32(&out).to_be_bytes());
+        out
+    }
+
+    fn unfilter(raw: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut prev = vec![0u8; stride];
+        for line in raw.chunks(stride + 1) {
+            let mut row = vec![0u8; stride];
+            for i in 0..stride {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = prev[i];
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                let predicted = match line[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    f => panic!("bad filter {}", f),
+                };
+                row[i] = line[i + 1].wrapping_add(predicted);
+            }
+            out.extend_from_slice(&row);
+            prev = row;
+        }
+        out
+    }
+
+    // splits a PNG into its chunks, checking every CRC on the way
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+        let mut pos = 8;
+        let mut out = Vec::new();
+        while pos < png.len() {
+            let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let crc = &png[pos + 8 + len..pos + 12 + len];
+            assert_eq!(crc, &crc32(body).to_be_bytes());
+            out.push(([body[0], body[1], body[2], body[3]], body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        out
+    }
+
+    fn round_trip(depth: BitDepth) {
+        let (width, height) = (7u32, 5u32);
+        let pixels: Vec<Color3> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let blue = match (x + y) % 3 {
+                    0 => 1.5,
+                    _ => 0.25,
+                };
+                Color3::new(x as f64 / 6.0, y as f64 / 4.0, blue)
+            })
+            .collect();
+        let mut png = Vec::new();
+        write_png(&mut png, width, height, &pixels, depth).unwrap();
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, vec![b"IHDR", b"IDAT", b"IEND"]);
+
+        let ihdr = &chunks[0].1;
+        assert_eq!(&ihdr[..8], &[0, 0, 0, 7, 0, 0, 0, 5]);
+        assert_eq!(&ihdr[8..], &[if depth == BitDepth::Eight { 8 } else { 16 }, 2, 0, 0, 0]);
+
+        let bpp = if depth == BitDepth::Eight { 3 } else { 6 };
+        let raw = inflate(&chunks[1].1);
+        let stride = width as usize * bpp;
+        assert_eq!(raw.len(), (stride + 1) * height as usize);
+        let bytes = unfilter(&raw, stride, bpp);
+
+        let mut expected = Vec::new();
+        for px in pixels.iter() {
+            for c in [px[Colors::R], px[Colors::G], px[Colors::B]] {
+                let v = clamp(c, 0.0, 1.0);
+                match depth {
+                    BitDepth::Eight => expected.push((v * 255.0).round() as u8),
+                    BitDepth::Sixteen => expected.extend_from_slice(&((v * 65535.0).round() as u16).to_be_bytes()),
+                }
+            }
+        }
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn eight_bit_round_trip() {
+        round_trip(BitDepth::Eight);
+    }
+
+    #[test]
+    fn sixteen_bit_round_trip() {
+        round_trip(BitDepth::Sixteen);
+    }
+
+    #[test]
+    fn long_runs_survive_compression() {
+        let data: Vec<u8> = (0..100_000).map(|i| if i % 1000 < 600 { 7 } else { (i % 251) as u8 }).collect();
+        assert_eq!(inflate(&zlib_compress(&data)), data);
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        for (width, height) in [(0, 3), (3, 0), (0, 0)] {
+            let mut png = Vec::new();
+            let err = write_png(&mut png, width, height, &[], BitDepth::Eight).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(png.is_empty());
+        }
+    }
+}
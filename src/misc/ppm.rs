@@ -1,30 +1,180 @@
-use std::io::{Stdout, Write};
+use std::io::{self, Write};
 
+use crate::image::Image;
 use crate::m::clamp;
 use crate::vectors::{Color3, Colors};
 
-pub fn write_color(std_str: &mut Stdout, v: &Color3) -> () {
+pub fn write_color<W: Write>(w: &mut W, v: &Color3) -> io::Result<()> {
     let ir = (255.999 * v[Colors::R]) as i32;
     let ig = (255.999 * v[Colors::G]) as i32;
     let ib = (255.999 * v[Colors::B]) as i32;
-    writeln!(std_str, "{} {} {}", ir, ig, ib).unwrap();
+    writeln!(w, "{} {} {}", ir, ig, ib)
 }
 
 
-pub fn write_color_avg(std_str: &mut Stdout, v: &Color3, samples_per_pixel: u32) -> () {
-    let mut r = v[Colors::R];
-    let mut g = v[Colors::G];
-    let mut b = v[Colors::B];
-
+/// Averages a sum of `samples_per_pixel` samples and applies gamma 2.
+pub fn to_display(v: &Color3, samples_per_pixel: u32) -> Color3 {
     let scale = 1.0 / samples_per_pixel as f64;
 
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
+    Color3::new((scale * v[Colors::R]).sqrt(),
+                (scale * v[Colors::G]).sqrt(),
+                (scale * v[Colors::B]).sqrt())
+}
+
+pub fn write_color_avg<W: Write>(w: &mut W, v: &Color3, samples_per_pixel: u32) -> io::Result<()> {
+    let c = to_display(v, samples_per_pixel);
+    let r = c[Colors::R];
+    let g = c[Colors::G];
+    let b = c[Colors::B];
 
 
     let ir = (256.0 * clamp(r, 0.0, 0.999)) as i32;
     let ig = (256.0 * clamp(g, 0.0, 0.999)) as i32;
     let ib = (256.0 * clamp(b, 0.0, 0.999)) as i32;
-    writeln!(std_str, "{} {} {}", ir, ig, ib).unwrap();
+    writeln!(w, "{} {} {}", ir, ig, ib)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PpmFormat {
+    /// `P3`, decimal text with 8 bits per channel.
+    Ascii,
+    /// `P6`, raw bytes with 8 bits per channel.
+    Binary,
+    /// `P6`, raw big-endian words with 16 bits per channel.
+    Binary16,
+}
+
+/// Writes a whole image as PPM. Pixels hold display-ready values in `[0, 1]`,
+/// anything outside is clamped.
+pub fn write_ppm<W: Write>(w: &mut W, image: &Image, format: PpmFormat) -> io::Result<()> {
+    let (magic, max) = match format {
+        PpmFormat::Ascii => ("P3", 255.0),
+        PpmFormat::Binary => ("P6", 255.0),
+        PpmFormat::Binary16 => ("P6", 65535.0),
+    };
+    write!(w, "{}\n{} {}\n{}\n", magic, image.width(), image.height(), max)?;
+
+    let quantize = |v: f64| (clamp(v, 0.0, 1.0) * max).round() as u16;
+    match format {
+        PpmFormat::Ascii => {
+            for px in image.pixels() {
+                writeln!(w, "{} {} {}", quantize(px[Colors::R]), quantize(px[Colors::G]), quantize(px[Colors::B]))?;
+            }
+        }
+        PpmFormat::Binary => {
+            let mut row = Vec::with_capacity(image.width() * 3);
+            for line in image.pixels().chunks(image.width().max(1)) {
+                row.clear();
+                for px in line {
+                    row.extend_from_slice(&[quantize(px[Colors::R]) as u8, quantize(px[Colors::G]) as u8, quantize(px[Colors::B]) as u8]);
+                }
+                w.write_all(&row)?;
+            }
+        }
+        PpmFormat::Binary16 => {
+            let mut row = Vec::with_capacity(image.width() * 6);
+            for line in image.pixels().chunks(image.width().max(1)) {
+                row.clear();
+                for px in line {
+                    for c in [px[Colors::R], px[Colors::G], px[Colors::B]] {
+                        row.extend_from_slice(&quantize(c).to_be_bytes());
+                    }
+                }
+                w.write_all(&row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes linear radiance as a little-endian Portable Float Map, values are kept as they are.
+pub fn write_pfm<W: Write>(w: &mut W, image: &Image) -> io::Result<()> {
+    // a negative scale marks little-endian data
+    write!(w, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+
+    let mut row = Vec::with_capacity(image.width() * 12);
+    // rows go from the bottom of the image to the top
+    for line in image.pixels().chunks(image.width().max(1)).rev() {
+        row.clear();
+        for px in line {
+            for c in [px[Colors::R], px[Colors::G], px[Colors::B]] {
+                row.extend_from_slice(&(c as f32).to_le_bytes());
+            }
+        }
+        w.write_all(&row)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2, top row red and green, bottom row blue and an out of range gray
+    fn image() -> Image {
+        Image::new(2, 2, vec![
+            Color3::new(1.0, 0.0, 0.0),
+            Color3::new(0.0, 0.5, 0.0),
+            Color3::new(0.0, 0.0, 1.0),
+            Color3::new(-0.5, 2.0, 0.25),
+        ])
+    }
+
+    #[test]
+    fn ascii_ppm() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, &image(), PpmFormat::Ascii).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "P3\n2 2\n255\n255 0 0\n0 128 0\n0 0 255\n0 255 64\n");
+    }
+
+    #[test]
+    fn binary_ppm() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, &image(), PpmFormat::Binary).unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], &[255, 0, 0, 0, 128, 0, 0, 0, 255, 0, 255, 64]);
+    }
+
+    #[test]
+    fn sixteen_bit_ppm_is_big_endian() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, &image(), PpmFormat::Binary16).unwrap();
+        let header = b"P6\n2 2\n65535\n";
+        assert_eq!(&out[..header.len()], header);
+        let data = &out[header.len()..];
+        assert_eq!(data.len(), 2 * 2 * 6);
+        assert_eq!(&data[..6], &[0xFF, 0xFF, 0, 0, 0, 0]);
+        // 0.5 * 65535 rounds to 32768
+        assert_eq!(&data[6..12], &[0, 0, 0x80, 0x00, 0, 0]);
+        assert_eq!(&data[18..], &[0, 0, 0xFF, 0xFF, 0x40, 0x00]);
+    }
+
+    #[test]
+    fn pfm_is_little_endian_and_bottom_up() {
+        let mut out = Vec::new();
+        write_pfm(&mut out, &image()).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // bottom row first, values are not clamped
+        assert_eq!(floats, vec![
+            0.0, 0.0, 1.0, -0.5, 2.0, 0.25,
+            1.0, 0.0, 0.0, 0.0, 0.5, 0.0,
+        ]);
+    }
+
+    #[test]
+    fn write_color_reports_errors() {
+        let mut full = [0u8; 4];
+        assert!(write_color(&mut &mut full[..], &Color3::new(1.0, 1.0, 1.0)).is_err());
+
+        let mut out = Vec::new();
+        write_color_avg(&mut out, &Color3::new(4.0, 1.0, 0.0), 4).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "255 128 0\n");
+    }
 }
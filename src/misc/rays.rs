@@ -1,33 +1,132 @@
-use std::f64::MAX;
-
-use crate::geometry::{HitRecord, Hittable};
+use crate::background::Background;
+use crate::geometry::{HitRecord, Hittable, HittableList};
+use crate::m::clamp;
 use crate::ray::Ray;
-use crate::vectors::{Axis, Color3, Vec3};
+use crate::sampler::Sampler;
+use crate::vectors::{Color3, Vec3};
 
-pub fn ray_color(r: &Ray, world: &dyn Hittable, depth: u32) -> Color3 {
-    if depth <= 0 {
-        return Color3::zero();
-    }
+/// Bounces after which paths may be terminated by Russian roulette.
+const RR_MIN_DEPTH: u32 = 3;
 
-    let mut hit_record = HitRecord::empty();
-    let is_hit = world.hit(r, 0.001, MAX, &mut hit_record);
+/// Radiance arriving along `r`. Rays escaping the scene pick up the `background`.
+///
+/// The path is followed iteratively while tracking its throughput. After
+/// `RR_MIN_DEPTH` bounces it survives with a probability given by the
+/// throughput and is reweighted accordingly, so the estimate stays unbiased;
+/// `max_depth` is a hard cap on the number of bounces.
+///
+/// At non-specular materials the next direction is drawn from an even mixture
+/// of the material's own choice and an importance sampled background
+/// direction, which keeps small bright parts of an environment map from
+/// showing up as fireflies.
+///
+/// Emitters in `lights` are also sampled directly with a shadow ray at those
+/// vertices. Light reaching a vertex can then be found by both strategies, the
+/// two estimates are combined with the power heuristic so that whichever
+/// density is larger for a direction dominates. Emitters missing from `lights`
+/// are still picked up by the bounces alone.
+pub fn ray_color(r: &Ray, world: &dyn Hittable, lights: &HittableList, background: &dyn Background, max_depth: u32, rng: &mut Sampler) -> Color3 {
+    let mut radiance = Color3::zero();
+    let mut throughput = Color3::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    // density of the last bounce direction, `None` after the camera or a specular bounce
+    let mut bounce_pdf: Option<f64> = None;
 
-    if is_hit {
-        let mut scattered = Ray::new(&Vec3::zero(), &Vec3::zero());
-        let mut attenuation = Color3::zero();
-        let mat_hit = hit_record.mat.scatter(r, &hit_record, &mut attenuation, &mut scattered);
-        if mat_hit {
-            return attenuation * ray_color(&scattered, world, depth - 1);
+    for depth in 0..max_depth {
+        let hit_record = match world.hit(&ray, 0.001, f64::MAX) {
+            Some(hit_record) => hit_record,
+            None => {
+                radiance += throughput * background.color(&ray);
+                break;
+            }
+        };
+
+        let emitted = hit_record.mat.emitted(&ray, &hit_record);
+        if emitted.max_component() > 0.0 {
+            let weight = match bounce_pdf {
+                Some(pdf) if !lights.elements().is_empty() => power_heuristic(pdf, lights.pdf_value(&ray.origin, &ray.direction)),
+                _ => 1.0,
+            };
+            radiance += weight * (throughput * emitted);
+        }
+
+        let srec = match hit_record.mat.scatter(&ray, &hit_record, rng) {
+            Some(srec) => srec,
+            // absorbed: nothing beyond this vertex reaches the camera
+            None => break,
+        };
+        let mut next = srec.scattered;
+
+        if srec.is_specular {
+            throughput = throughput * srec.attenuation;
+            bounce_pdf = None;
         } else {
-            Color3::zero();
+            let mat = hit_record.mat;
+            let env_dir = background.sample(rng).map(|(dir, _)| dir);
+            let mixture_pdf = |dir: &Vec3| {
+                let pdf = mat.pdf(&ray, &hit_record, dir);
+                if env_dir.is_some() { 0.5 * pdf + 0.5 * background.pdf(dir) } else { pdf }
+            };
+
+            if !lights.elements().is_empty() {
+                radiance += throughput * direct_light(world, lights, &ray, &hit_record, &mixture_pdf, rng);
+            }
+
+            let (direction, from_background) = match env_dir {
+                Some(dir) if rng.rand_f() < 0.5 => (dir, true),
+                _ => (srec.scattered.direction, false),
+            };
+            let pdf = mixture_pdf(&direction);
+            if pdf <= 0.0 {
+                break;
+            }
+            // the material's own sample keeps its attenuation, which is exact
+            let weight = if from_background { mat.eval(&ray, &hit_record, &direction) / pdf } else { srec.attenuation * (srec.pdf / pdf) };
+            if weight.max_component() <= 0.0 {
+                break;
+            }
+            throughput = throughput * weight;
+            next = Ray::new(&hit_record.p, &direction);
+            bounce_pdf = Some(pdf);
         }
-        //
-        // let target = hit_record.p + hit_record.normal + Vec3::random_unit_vector();
-        //
-        // return 0.5 * ray_color(&Ray::new(&hit_record.p, &(target - hit_record.p)), world, depth - 1);
+
+        if depth + 1 >= RR_MIN_DEPTH {
+            let survival = clamp(throughput.max_component(), 0.05, 1.0);
+            if rng.rand_f() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        ray = next;
+    }
+
+    radiance
+}
+
+// one light sample, whatever the shadow ray hits first decides whether the
+// light is visible; `bounce_pdf` gives the density of the competing strategy
+fn direct_light(world: &dyn Hittable, lights: &HittableList, ray_in: &Ray, hit_record: &HitRecord,
+                bounce_pdf: &dyn Fn(&Vec3) -> f64, rng: &mut Sampler) -> Color3 {
+    let direction = lights.random(&hit_record.p, rng);
+    let light_pdf = lights.pdf_value(&hit_record.p, &direction);
+    if light_pdf <= 0.0 {
+        return Color3::zero();
+    }
+    let f = hit_record.mat.eval(ray_in, hit_record, &direction);
+    if f.max_component() <= 0.0 {
+        return Color3::zero();
     }
+    let shadow = Ray::new(&hit_record.p, &direction);
+    let light_hit = match world.hit(&shadow, 0.001, f64::MAX) {
+        Some(light_hit) => light_hit,
+        None => return Color3::zero(),
+    };
+    let weight = power_heuristic(light_pdf, bounce_pdf(&direction));
+    f * light_hit.mat.emitted(&shadow, &light_hit) * (weight / light_pdf)
+}
 
-    let unit_direction = r.direction.unit_vec();
-    let t = 0.5 * (unit_direction[Axis::Y] + 1.0);
-    (1.0 - t) * Color3::new(1.0, 1.0, 1.0) + t * Color3::new(0.5, 0.7, 1.0)
-}
\ No newline at end of file
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    a / (a + b)
+}
@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::background::{Background, Gradient};
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::geometry::{Hittable, HittableList};
+use crate::rays::ray_color;
+use crate::sampler::Sampler;
+
+/// Renders an image by splitting it into square tiles which are picked up
+/// by a pool of worker threads.
+///
+/// Every pixel draws its random numbers from its own `Sampler` derived from
+/// `seed` and the pixel index, so for a given seed the result does not depend
+/// on the number of threads, the tile size or the order in which tiles are
+/// finished. Without a seed a fresh one is picked for every render.
+///
+/// Emitters added to `lights` are sampled directly; they must also be part
+/// of the rendered world.
+pub struct Renderer {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_px: u32,
+    pub max_depth: u32,
+    pub threads: usize,
+    pub tile_size: u32,
+    pub seed: Option<u64>,
+    pub background: Arc<dyn Background>,
+    pub lights: HittableList,
+    pub progress: bool,
+}
+
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+impl Renderer {
+    pub fn new(image_width: u32, image_height: u32, samples_per_px: u32, max_depth: u32) -> Renderer {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Renderer { image_width, image_height, samples_per_px, max_depth, threads, tile_size: 16, seed: None, background: Arc::new(Gradient::sky()), lights: HittableList::empty(), progress: false }
+    }
+
+    /// Renders `world` as seen by `camera` into a new film.
+    pub fn render(&self, camera: &Camera, world: &dyn Hittable) -> Film {
+        let film = Film::new(self.image_width, self.image_height);
+        let tiles = self.tiles();
+        let next_tile = AtomicUsize::new(0);
+        let done_tiles = AtomicUsize::new(0);
+        let seed = self.seed.unwrap_or_else(rand::random);
+
+        thread::scope(|s| {
+            for _ in 0..self.threads.max(1) {
+                s.spawn(|| {
+                    loop {
+                        let idx = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if idx >= tiles.len() {
+                            break;
+                        }
+                        self.render_tile(&tiles[idx], camera, world, seed, &film);
+
+                        let done = done_tiles.fetch_add(1, Ordering::Relaxed) + 1;
+                        if self.progress {
+                            eprintln!("Tiles remaining {}", tiles.len() - done);
+                        }
+                    }
+                });
+            }
+        });
+
+        film
+    }
+
+    fn render_tile(&self, tile: &Tile, camera: &Camera, world: &dyn Hittable, seed: u64, film: &Film) {
+        for y in tile.y0..tile.y1 {
+            let j = self.image_height - 1 - y;
+            for i in tile.x0..tile.x1 {
+                let mut rng = Sampler::derive(seed, y as u64 * self.image_width as u64 + i as u64);
+
+                for _s in 0..self.samples_per_px {
+                    let u = (i as f64 + rng.rand_f()) / (self.image_width as f64 - 1.0);
+                    let v = (j as f64 + rng.rand_f()) / (self.image_height as f64 - 1.0);
+                    let r = camera.get_ray(u, v, &mut rng);
+                    let color = ray_color(&r, world, &self.lights, self.background.as_ref(), self.max_depth, &mut rng);
+                    film.add_sample(i, y, &color, 1.0);
+                }
+            }
+        }
+    }
+
+    fn tiles(&self) -> Vec<Tile> {
+        let size = self.tile_size.max(1);
+        let mut tiles = Vec::new();
+        for y0 in (0..self.image_height).step_by(size as usize) {
+            for x0 in (0..self.image_width).step_by(size as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: (x0 + size).min(self.image_width),
+                    y1: (y0 + size).min(self.image_height),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Sphere;
+    use crate::materials::{Dielectric, DiffuseLight, Lambertian, Metal};
+    use crate::vectors::{Point3, Vec3};
+
+    fn scene() -> (Camera, HittableList, HittableList) {
+        let mut world = HittableList::empty();
+        let mut lights = HittableList::empty();
+        world.add(Arc::new(Sphere::new_f(0.0, -100.5, -1.0, 100.0, Arc::new(Lambertian::new_f(0.5, 0.6, 0.3)))));
+        world.add(Arc::new(Sphere::new_f(-1.0, 0.0, -1.0, 0.5, Arc::new(Dielectric::new(1.5)))));
+        world.add(Arc::new(Sphere::new_f(0.0, 0.0, -1.0, 0.5, Arc::new(Lambertian::new_f(0.7, 0.3, 0.3)))));
+        world.add(Arc::new(Sphere::new_f(1.0, 0.0, -1.0, 0.5, Arc::new(Metal::new_f(0.8, 0.6, 0.2, 0.3)))));
+        let lamp = Arc::new(Sphere::new_f(0.0, 2.0, -1.0, 0.3, Arc::new(DiffuseLight::new_f(4.0, 4.0, 4.0))));
+        world.add(lamp.clone());
+        lights.add(lamp);
+
+        let camera = Camera::new(Point3::new(0.0, 0.5, 2.0), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+                                 60.0, 23.0 / 17.0, 0.1, 3.0);
+        (camera, world, lights)
+    }
+
+    fn render(threads: usize, tile_size: u32) -> Vec<u64> {
+        let (camera, world, lights) = scene();
+        let mut renderer = Renderer::new(23, 17, 8, 10);
+        renderer.seed = Some(1234);
+        renderer.threads = threads;
+        renderer.tile_size = tile_size;
+        renderer.lights = lights;
+        let film = renderer.render(&camera, &world);
+        film.to_image().pixels().iter().flat_map(|c| (0..3).map(move |i| c[i].to_bits())).collect()
+    }
+
+    #[test]
+    fn seeded_output_does_not_depend_on_threads_or_tiles() {
+        let reference = render(1, 16);
+        assert!(reference.iter().any(|&bits| f64::from_bits(bits) > 0.0));
+        assert_eq!(render(4, 16), reference);
+        assert_eq!(render(4, 5), reference);
+        assert_eq!(render(3, 1), reference);
+    }
+}
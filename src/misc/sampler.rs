@@ -0,0 +1,70 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+/// Source of all random numbers used while building and rendering a scene.
+///
+/// The same seed always yields the same sequence, which makes renders
+/// reproducible bit for bit.
+pub struct Sampler {
+    rng: StdRng,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Sampler {
+        Sampler { rng: StdRng::seed_from_u64(seed) }
+    }
+
+    pub fn from_entropy() -> Sampler {
+        Sampler { rng: StdRng::from_entropy() }
+    }
+
+    /// Independent sampler for the `stream`-th consumer (pixel, tile, ...) of `seed`.
+    pub fn derive(seed: u64, stream: u64) -> Sampler {
+        Sampler::new(mix_seed(seed, stream))
+    }
+
+    pub fn rand_f(&mut self) -> f64 {
+        self.rng.gen_range(0.0, 1.0)
+    }
+
+    pub fn rand_f_mm(&mut self, min: f64, max: f64) -> f64 {
+        self.rng.gen_range(min, max)
+    }
+}
+
+// splitmix64 finalizer, spreads neighbouring stream indices over the whole seed space
+fn mix_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed.wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draws(mut rng: Sampler) -> Vec<u64> {
+        (0..64).map(|_| rng.rand_f().to_bits()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        assert_eq!(draws(Sampler::new(3)), draws(Sampler::new(3)));
+        assert_ne!(draws(Sampler::new(3)), draws(Sampler::new(4)));
+        assert_eq!(draws(Sampler::derive(3, 10)), draws(Sampler::derive(3, 10)));
+        assert_ne!(draws(Sampler::derive(3, 10)), draws(Sampler::derive(3, 11)));
+        assert_ne!(draws(Sampler::derive(3, 10)), draws(Sampler::derive(4, 10)));
+    }
+
+    #[test]
+    fn draws_stay_in_range() {
+        let mut rng = Sampler::new(9);
+        for _ in 0..10_000 {
+            let x = rng.rand_f();
+            assert!((0.0..1.0).contains(&x));
+            let y = rng.rand_f_mm(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&y));
+        }
+    }
+}
@@ -0,0 +1,801 @@
+//! Text scene files.
+//!
+//! Every non-empty line holds one statement, `#` starts a comment and
+//! arguments are separated by whitespace; names and paths containing spaces
+//! can be put in double quotes, inside which `\"` and `\\` stand for a quote
+//! and a backslash.
+//!
+//! ```text
+//! render width 800 height 450 spp 100 depth 50 seed 7
+//! camera from 13 2 3 at 0 0 0 up 0 1 0 fov 20 aperture 0.1 focus 10
+//! background sky                      # or: color r g b
+//!                                     #     gradient r g b r g b (bottom, top)
+//!                                     #     hdr PATH rotation 90 intensity 1
+//! material ground lambertian 0.5 0.5 0.5
+//! material steel metal 0.7 0.6 0.5 fuzz 0.1
+//! material glass dielectric 1.5
+//! material lamp light 4 4 4
+//! sphere ground 0 -1000 0 1000
+//! push
+//! translate 0 1 0
+//! rotate y 45                         # axis x, y or z, angle in degrees
+//! scale 2                             # or: scale x y z
+//! triangle lamp -1 0 0  1 0 0  0 1 0
+//! pop
+//! quad ground -1 0 -1  2 0 0  0 0 2   # corner, then the two edges
+//! box steel 0 0 0  1 1 1              # opposite corners
+//! ```
+//!
+//! `render` and `camera` settings are all optional and may come in any order,
+//! the camera aspect ratio follows the image size and the focus distance
+//! defaults to the distance between `from` and `at`.
+//!
+//! Transforms apply to the primitives that follow them, the last one listed
+//! acting first, and are baked into the geometry; `push` and `pop` save and
+//! restore the current transform. Spheres under a non-uniform scale become
+//! transformed instances instead.
+//! Primitives made of a `light` material are also sampled directly.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::background::{Background, Equirectangular, Gradient, SolidColor};
+use crate::camera::Camera;
+use crate::geometry::{Cuboid, Hittable, HittableList, Quad, Sphere, Transformed, Triangle};
+use crate::hdr::load_hdr;
+use crate::materials::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::render::Renderer;
+use crate::vectors::{Axis, Color3, Point3, Transform, Vec3};
+
+/// Problem found while reading a scene, `line` and `column` are 1-based and
+/// zero when the position is not known, e.g. for files that could not be read.
+#[derive(Debug, Clone)]
+pub struct SceneError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl Error for SceneError {}
+
+/// Statements of a scene file in the order they were written.
+///
+/// Formatting it with `Display` gives scene text which parses back to the same description.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SceneDescription {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Render(RenderSettings),
+    Camera(CameraSettings),
+    Background(BackgroundDesc),
+    Material(String, MaterialDesc),
+    Sphere { material: String, center: Point3, radius: f64 },
+    Triangle { material: String, vertices: [Point3; 3] },
+    Quad { material: String, corner: Point3, u: Vec3, v: Vec3 },
+    /// Axis-aligned box given by two opposite corners, before transforms.
+    Box { material: String, corners: [Point3; 2] },
+    Translate(Vec3),
+    Rotate(Axis, f64),
+    Scale(Vec3),
+    Push,
+    Pop,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_px: u32,
+    pub max_depth: u32,
+    pub seed: Option<u64>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings { width: 400, height: 225, samples_per_px: 100, max_depth: 50, seed: None }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: Option<f64>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            lookfrom: Point3::new(0.0, 0.0, 1.0),
+            lookat: Point3::zero(),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_dist: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackgroundDesc {
+    Sky,
+    Color(Color3),
+    Gradient(Color3, Color3),
+    /// Equirectangular Radiance HDR image, relative paths start at the scene file.
+    Hdr { path: String, rotation: f64, intensity: f64 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MaterialDesc {
+    Lambertian(Color3),
+    Metal(Color3, f64),
+    Dielectric(f64),
+    Light(Color3),
+}
+
+impl MaterialDesc {
+    fn build(&self) -> Arc<dyn Material> {
+        match self {
+            MaterialDesc::Lambertian(c) => Arc::new(Lambertian::new(c)),
+            MaterialDesc::Metal(c, fuzz) => Arc::new(Metal::new(c, *fuzz)),
+            MaterialDesc::Dielectric(ir) => Arc::new(Dielectric::new(*ir)),
+            MaterialDesc::Light(c) => Arc::new(DiffuseLight::new(c)),
+        }
+    }
+}
+
+/// Everything needed to render a scene file.
+pub struct Scene {
+    pub settings: RenderSettings,
+    pub camera: Camera,
+    pub world: HittableList,
+    /// Primitives made of a `light` material, also part of `world`.
+    pub lights: HittableList,
+    pub background: Arc<dyn Background>,
+}
+
+impl Scene {
+    /// Renderer set up with the image size, sampling, background and lights of the scene.
+    pub fn renderer(&self) -> Renderer {
+        let s = &self.settings;
+        let mut renderer = Renderer::new(s.width, s.height, s.samples_per_px, s.max_depth);
+        renderer.seed = s.seed;
+        renderer.background = self.background.clone();
+        renderer.lights = self.lights.clone();
+        renderer
+    }
+}
+
+/// Reads and builds a scene file, resolving HDR paths relative to its directory.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let file = path.display().to_string();
+    let text = fs::read_to_string(path)
+        .map_err(|e| SceneError { file: file.clone(), line: 0, column: 0, message: e.to_string() })?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&text, &file)?.build(base_dir)
+}
+
+/// Parses scene text, `file` only names the source in errors.
+///
+/// Besides the syntax this checks that materials are defined before they
+/// are used, that every `pop` has a matching `push` and that scale factors are
+/// not zero.
+pub fn parse_scene(text: &str, file: &str) -> Result<SceneDescription, SceneError> {
+    let mut statements = Vec::new();
+    let mut materials = HashSet::new();
+    // number of `push` statements not closed yet
+    let mut depth = 0;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |column: usize, message: String| SceneError { file: file.to_string(), line: line_no, column, message };
+        let tokens = tokenize(line).map_err(|(column, message)| err(column, message))?;
+        if tokens.is_empty() {
+            continue;
+        }
+        let mut args = Args { tokens: &tokens[1..], pos: 0, end_column: line.chars().count() + 1 };
+        let keyword = &tokens[0];
+
+        let statement = match keyword.text.as_str() {
+            "render" => Statement::Render(parse_render(&mut args).map_err(|(c, m)| err(c, m))?),
+            "camera" => Statement::Camera(parse_camera(&mut args).map_err(|(c, m)| err(c, m))?),
+            "background" => Statement::Background(parse_background(&mut args).map_err(|(c, m)| err(c, m))?),
+            "material" => {
+                let name = args.next("material name").map_err(|(c, m)| err(c, m))?;
+                if materials.contains(&name.text) {
+                    return Err(err(name.column, format!("material {} is already defined", name.text)));
+                }
+                let desc = parse_material(&mut args).map_err(|(c, m)| err(c, m))?;
+                materials.insert(name.text.clone());
+                Statement::Material(name.text.clone(), desc)
+            }
+            "sphere" | "triangle" | "quad" | "box" => {
+                let name = args.next("material name").map_err(|(c, m)| err(c, m))?;
+                if !materials.contains(&name.text) {
+                    return Err(err(name.column, format!("unknown material {}", name.text)));
+                }
+                let material = name.text.clone();
+                match keyword.text.as_str() {
+                    "sphere" => {
+                        let center = args.vec3("sphere center").map_err(|(c, m)| err(c, m))?;
+                        let radius = args.number("sphere radius").map_err(|(c, m)| err(c, m))?;
+                        Statement::Sphere { material, center, radius }
+                    }
+                    "triangle" => {
+                        let mut vertices = [Point3::zero(); 3];
+                        for v in vertices.iter_mut() {
+                            *v = args.vec3("triangle vertex").map_err(|(c, m)| err(c, m))?;
+                        }
+                        Statement::Triangle { material, vertices }
+                    }
+                    "quad" => {
+                        let corner = args.vec3("quad corner").map_err(|(c, m)| err(c, m))?;
+                        let u = args.vec3("quad edge").map_err(|(c, m)| err(c, m))?;
+                        let v = args.vec3("quad edge").map_err(|(c, m)| err(c, m))?;
+                        Statement::Quad { material, corner, u, v }
+                    }
+                    _ => {
+                        let a = args.vec3("box corner").map_err(|(c, m)| err(c, m))?;
+                        let b = args.vec3("box corner").map_err(|(c, m)| err(c, m))?;
+                        Statement::Box { material, corners: [a, b] }
+                    }
+                }
+            }
+            "translate" => Statement::Translate(args.vec3("offset").map_err(|(c, m)| err(c, m))?),
+            "rotate" => {
+                let axis = args.next("rotation axis").map_err(|(c, m)| err(c, m))?;
+                let axis = match axis.text.as_str() {
+                    "x" => Axis::X,
+                    "y" => Axis::Y,
+                    "z" => Axis::Z,
+                    other => return Err(err(axis.column, format!("rotation axis must be x, y or z, got {}", other))),
+                };
+                Statement::Rotate(axis, args.number("angle").map_err(|(c, m)| err(c, m))?)
+            }
+            "scale" => {
+                let x = args.number("scale").map_err(|(c, m)| err(c, m))?;
+                let factors = if args.is_empty() {
+                    Vec3::new(x, x, x)
+                } else {
+                    let y = args.number("y scale").map_err(|(c, m)| err(c, m))?;
+                    let z = args.number("z scale").map_err(|(c, m)| err(c, m))?;
+                    Vec3::new(x, y, z)
+                };
+                if factors[0] == 0.0 || factors[1] == 0.0 || factors[2] == 0.0 {
+                    return Err(err(keyword.column, "scale factors can not be zero".to_string()));
+                }
+                Statement::Scale(factors)
+            }
+            "push" => {
+                depth += 1;
+                Statement::Push
+            }
+            "pop" => {
+                if depth == 0 {
+                    return Err(err(keyword.column, "pop without a matching push".to_string()));
+                }
+                depth -= 1;
+                Statement::Pop
+            }
+            other => return Err(err(keyword.column, format!("unknown statement {}", other))),
+        };
+        args.finish().map_err(|(c, m)| err(c, m))?;
+        statements.push(statement);
+    }
+
+    Ok(SceneDescription { statements })
+}
+
+impl SceneDescription {
+    /// Builds the camera, geometry and background. Errors carry no position,
+    /// a description coming from `parse_scene` can only fail to load its HDR image.
+    pub fn build(&self, base_dir: &Path) -> Result<Scene, SceneError> {
+        let err = |file: String, message: String| SceneError { file, line: 0, column: 0, message };
+        let mut settings = RenderSettings::default();
+        let mut camera = CameraSettings::default();
+        let mut background = BackgroundDesc::Sky;
+        let mut materials: HashMap<&str, (Arc<dyn Material>, bool)> = HashMap::new();
+        let mut transforms = TransformStack::new();
+        let mut world = HittableList::empty();
+        let mut lights = HittableList::empty();
+
+        for statement in self.statements.iter() {
+            let transform = transforms.current();
+            let material_of = |name: &String| match materials.get(name.as_str()) {
+                Some((m, _)) => Ok(m.clone()),
+                None => Err(err(String::new(), format!("unknown material {}", name))),
+            };
+            let object: Arc<dyn Hittable> = match statement {
+                Statement::Render(s) => { settings = *s; continue; }
+                Statement::Camera(c) => { camera = *c; continue; }
+                Statement::Background(b) => { background = b.clone(); continue; }
+                Statement::Material(name, desc) => {
+                    materials.insert(name, (desc.build(), matches!(desc, MaterialDesc::Light(_))));
+                    continue;
+                }
+                Statement::Sphere { material, center, radius } => match uniform_scale(&transform) {
+                    Some(scale) => Arc::new(Sphere::new(&transform.point(center), radius * scale, material_of(material)?)),
+                    None => Arc::new(Transformed::new(Arc::new(Sphere::new(center, *radius, material_of(material)?)), transform)),
+                },
+                Statement::Triangle { material, vertices } => {
+                    let [a, b, c] = vertices.map(|v| transform.point(&v));
+                    Arc::new(Triangle::new(&a, &b, &c, material_of(material)?))
+                }
+                Statement::Quad { material, corner, u, v } => {
+                    Arc::new(Quad::new(&transform.point(corner), &transform.vector(u), &transform.vector(v), material_of(material)?))
+                }
+                Statement::Box { material, corners } => {
+                    let d = corners[1] - corners[0];
+                    let edges = [Vec3::new(d[0], 0.0, 0.0), Vec3::new(0.0, d[1], 0.0), Vec3::new(0.0, 0.0, d[2])]
+                        .map(|e| transform.vector(&e));
+                    Arc::new(Cuboid::spanned(&transform.point(&corners[0]), &edges[0], &edges[1], &edges[2], material_of(material)?))
+                }
+                Statement::Translate(_) | Statement::Rotate(..) | Statement::Scale(_) | Statement::Push | Statement::Pop => {
+                    transforms.apply(statement);
+                    continue;
+                }
+            };
+
+            let material = match statement {
+                Statement::Sphere { material, .. } | Statement::Triangle { material, .. }
+                | Statement::Quad { material, .. } | Statement::Box { material, .. } => material.as_str(),
+                _ => unreachable!(),
+            };
+            if materials[material].1 {
+                lights.add(object.clone());
+            }
+            world.add(object);
+        }
+
+        let aspect_ratio = settings.width as f64 / settings.height as f64;
+        let focus_dist = camera.focus_dist.unwrap_or_else(|| (camera.lookfrom - camera.lookat).length());
+        let camera = Camera::new(camera.lookfrom, camera.lookat, camera.vup, camera.vfov, aspect_ratio, camera.aperture, focus_dist);
+
+        let background: Arc<dyn Background> = match background {
+            BackgroundDesc::Sky => Arc::new(Gradient::sky()),
+            BackgroundDesc::Color(c) => Arc::new(SolidColor::new(&c)),
+            BackgroundDesc::Gradient(bottom, top) => Arc::new(Gradient::new(&bottom, &top)),
+            BackgroundDesc::Hdr { path, rotation, intensity } => {
+                let path = base_dir.join(path);
+                let image = load_hdr(&path).map_err(|e| err(path.display().to_string(), e.to_string()))?;
+                let mut env = Equirectangular::new(image);
+                env.set_rotation(rotation);
+                env.set_intensity(intensity);
+                Arc::new(env)
+            }
+        };
+
+        Ok(Scene { settings, camera, world, lights, background })
+    }
+}
+
+impl fmt::Display for SceneDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for statement in self.statements.iter() {
+            writeln!(f, "{}", statement)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Render(s) => {
+                write!(f, "render width {} height {} spp {} depth {}", s.width, s.height, s.samples_per_px, s.max_depth)?;
+                if let Some(seed) = s.seed {
+                    write!(f, " seed {}", seed)?;
+                }
+                Ok(())
+            }
+            Statement::Camera(c) => {
+                write!(f, "camera from {} at {} up {} fov {} aperture {}",
+                       Triple(&c.lookfrom), Triple(&c.lookat), Triple(&c.vup), c.vfov, c.aperture)?;
+                if let Some(focus) = c.focus_dist {
+                    write!(f, " focus {}", focus)?;
+                }
+                Ok(())
+            }
+            Statement::Background(BackgroundDesc::Sky) => write!(f, "background sky"),
+            Statement::Background(BackgroundDesc::Color(c)) => write!(f, "background color {}", Triple(c)),
+            Statement::Background(BackgroundDesc::Gradient(bottom, top)) =>
+                write!(f, "background gradient {} {}", Triple(bottom), Triple(top)),
+            Statement::Background(BackgroundDesc::Hdr { path, rotation, intensity }) =>
+                write!(f, "background hdr {} rotation {} intensity {}", quoted(path), rotation, intensity),
+            Statement::Material(name, desc) => {
+                write!(f, "material {} ", quoted(name))?;
+                match desc {
+                    MaterialDesc::Lambertian(c) => write!(f, "lambertian {}", Triple(c)),
+                    MaterialDesc::Metal(c, fuzz) => write!(f, "metal {} fuzz {}", Triple(c), fuzz),
+                    MaterialDesc::Dielectric(ir) => write!(f, "dielectric {}", ir),
+                    MaterialDesc::Light(c) => write!(f, "light {}", Triple(c)),
+                }
+            }
+            Statement::Sphere { material, center, radius } => write!(f, "sphere {} {} {}", quoted(material), Triple(center), radius),
+            Statement::Triangle { material, vertices } => write!(f, "triangle {}  {}  {}  {}",
+                                                                quoted(material), Triple(&vertices[0]), Triple(&vertices[1]), Triple(&vertices[2])),
+            Statement::Quad { material, corner, u, v } => write!(f, "quad {}  {}  {}  {}", quoted(material), Triple(corner), Triple(u), Triple(v)),
+            Statement::Box { material, corners } => write!(f, "box {}  {}  {}", quoted(material), Triple(&corners[0]), Triple(&corners[1])),
+            Statement::Translate(v) => write!(f, "translate {}", Triple(v)),
+            Statement::Rotate(axis, degrees) => {
+                let axis = match axis {
+                    Axis::X => "x",
+                    Axis::Y => "y",
+                    Axis::Z => "z",
+                };
+                write!(f, "rotate {} {}", axis, degrees)
+            }
+            Statement::Scale(v) if v[0] == v[1] && v[1] == v[2] => write!(f, "scale {}", v[0]),
+            Statement::Scale(v) => write!(f, "scale {}", Triple(v)),
+            Statement::Push => write!(f, "push"),
+            Statement::Pop => write!(f, "pop"),
+        }
+    }
+}
+
+// prints the three components of a vector separated by spaces
+struct Triple<'a>(&'a Vec3);
+
+impl fmt::Display for Triple<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+// puts names and paths in quotes when they would not read back as a single word
+fn quoted(s: &str) -> String {
+    if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '#' || c == '"') {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn parse_render(args: &mut Args<'_>) -> Result<RenderSettings, (usize, String)> {
+    let mut settings = RenderSettings::default();
+    while let Some(key) = args.try_next() {
+        match key.text.as_str() {
+            "width" => settings.width = args.positive("image width")?,
+            "height" => settings.height = args.positive("image height")?,
+            "spp" => settings.samples_per_px = args.positive("samples per pixel")?,
+            "depth" => settings.max_depth = args.positive("maximum depth")?,
+            "seed" => {
+                let token = args.next("seed")?;
+                settings.seed = Some(token.text.parse().map_err(|_| (token.column, format!("bad seed {}", token.text)))?);
+            }
+            other => return Err((key.column, format!("unknown render setting {}", other))),
+        }
+    }
+    Ok(settings)
+}
+
+fn parse_camera(args: &mut Args<'_>) -> Result<CameraSettings, (usize, String)> {
+    let mut camera = CameraSettings::default();
+    while let Some(key) = args.try_next() {
+        match key.text.as_str() {
+            "from" => camera.lookfrom = args.vec3("camera position")?,
+            "at" => camera.lookat = args.vec3("camera target")?,
+            "up" => camera.vup = args.vec3("up vector")?,
+            "fov" => camera.vfov = args.number("field of view")?,
+            "aperture" => camera.aperture = args.number("aperture")?,
+            "focus" => camera.focus_dist = Some(args.number("focus distance")?),
+            other => return Err((key.column, format!("unknown camera setting {}", other))),
+        }
+    }
+    Ok(camera)
+}
+
+fn parse_background(args: &mut Args<'_>) -> Result<BackgroundDesc, (usize, String)> {
+    let kind = args.next("background kind")?;
+    match kind.text.as_str() {
+        "sky" => Ok(BackgroundDesc::Sky),
+        "color" => Ok(BackgroundDesc::Color(args.vec3("color")?)),
+        "gradient" => {
+            let bottom = args.vec3("bottom color")?;
+            Ok(BackgroundDesc::Gradient(bottom, args.vec3("top color")?))
+        }
+        "hdr" => {
+            let path = args.next("image path")?.text.clone();
+            let (mut rotation, mut intensity) = (0.0, 1.0);
+            while let Some(key) = args.try_next() {
+                match key.text.as_str() {
+                    "rotation" => rotation = args.number("rotation")?,
+                    "intensity" => intensity = args.number("intensity")?,
+                    other => return Err((key.column, format!("unknown hdr setting {}", other))),
+                }
+            }
+            Ok(BackgroundDesc::Hdr { path, rotation, intensity })
+        }
+        other => Err((kind.column, format!("unknown background {}, expected sky, color, gradient or hdr", other))),
+    }
+}
+
+fn parse_material(args: &mut Args<'_>) -> Result<MaterialDesc, (usize, String)> {
+    let kind = args.next("material kind")?;
+    match kind.text.as_str() {
+        "lambertian" => Ok(MaterialDesc::Lambertian(args.vec3("albedo")?)),
+        "metal" => {
+            let albedo = args.vec3("albedo")?;
+            let mut fuzz = 0.0;
+            if let Some(key) = args.try_next() {
+                if key.text != "fuzz" {
+                    return Err((key.column, format!("unknown metal setting {}", key.text)));
+                }
+                fuzz = args.number("fuzz")?;
+            }
+            Ok(MaterialDesc::Metal(albedo, fuzz))
+        }
+        "dielectric" => {
+            let column = args.column();
+            let ir = args.number("index of refraction")?;
+            if ir <= 0.0 {
+                return Err((column, format!("index of refraction must be positive, got {}", ir)));
+            }
+            Ok(MaterialDesc::Dielectric(ir))
+        }
+        "light" => Ok(MaterialDesc::Light(args.vec3("emitted color")?)),
+        other => Err((kind.column, format!("unknown material kind {}, expected lambertian, metal, dielectric or light", other))),
+    }
+}
+
+struct Token {
+    text: String,
+    column: usize,
+}
+
+// splits a line into words, honoring double quotes and dropping comments
+fn tokenize(line: &str) -> Result<Vec<Token>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().enumerate().peekable();
+    while let Some(&(idx, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    // other backslashes are kept, so Windows paths need no escaping
+                    Some((_, '\\')) if matches!(chars.peek(), Some((_, '"')) | Some((_, '\\'))) => {
+                        text.push(chars.next().unwrap().1);
+                    }
+                    Some((_, c)) => text.push(c),
+                    None => return Err((idx + 1, "unterminated quote".to_string())),
+                }
+            }
+            tokens.push(Token { text, column: idx + 1 });
+        } else {
+            let mut text = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == '#' {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            tokens.push(Token { text, column: idx + 1 });
+        }
+    }
+    Ok(tokens)
+}
+
+// arguments following the keyword of a statement, errors carry the column they refer to
+struct Args<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    end_column: usize,
+}
+
+impl<'a> Args<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end_column, |t| t.column)
+    }
+
+    fn try_next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'a Token, (usize, String)> {
+        let column = self.column();
+        self.try_next().ok_or_else(|| (column, format!("expected {}", what)))
+    }
+
+    fn number(&mut self, what: &str) -> Result<f64, (usize, String)> {
+        let token = self.next(what)?;
+        match token.text.parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => Err((token.column, format!("bad number {} for {}", token.text, what))),
+        }
+    }
+
+    fn positive(&mut self, what: &str) -> Result<u32, (usize, String)> {
+        let token = self.next(what)?;
+        match token.text.parse::<u32>() {
+            Ok(v) if v > 0 => Ok(v),
+            _ => Err((token.column, format!("expected a positive integer for {}, got {}", what, token.text))),
+        }
+    }
+
+    fn vec3(&mut self, what: &str) -> Result<Vec3, (usize, String)> {
+        Ok(Vec3::new(self.number(what)?, self.number(what)?, self.number(what)?))
+    }
+
+    fn finish(&self) -> Result<(), (usize, String)> {
+        match self.tokens.get(self.pos) {
+            Some(token) => Err((token.column, format!("unexpected {}", token.text))),
+            None => Ok(()),
+        }
+    }
+}
+
+// scale factor when the linear part is a rotation times a uniform scale
+fn uniform_scale(transform: &Transform) -> Option<f64> {
+    let cols = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)].map(|e| transform.vector(&e));
+    let s = cols[0].length();
+    let eps = 1e-9 * s.max(1.0);
+    let same_length = cols.iter().all(|c| (c.length() - s).abs() <= eps);
+    let orthogonal = cols[0].dot(cols[1]).abs() <= eps * s && cols[0].dot(cols[2]).abs() <= eps * s
+        && cols[1].dot(cols[2]).abs() <= eps * s;
+    if same_length && orthogonal { Some(s) } else { None }
+}
+
+struct TransformStack {
+    stack: Vec<Transform>,
+}
+
+impl TransformStack {
+    fn new() -> TransformStack {
+        TransformStack { stack: vec![Transform::identity()] }
+    }
+
+    fn current(&self) -> Transform {
+        *self.stack.last().unwrap()
+    }
+
+    fn apply(&mut self, statement: &Statement) {
+        let op = match statement {
+            Statement::Translate(v) => Transform::translate(v),
+            Statement::Rotate(axis, degrees) => {
+                let axis = match axis {
+                    Axis::X => Vec3::new(1.0, 0.0, 0.0),
+                    Axis::Y => Vec3::new(0.0, 1.0, 0.0),
+                    Axis::Z => Vec3::new(0.0, 0.0, 1.0),
+                };
+                Transform::rotate(&axis, *degrees)
+            }
+            Statement::Scale(v) => Transform::scale(v),
+            Statement::Push => {
+                self.stack.push(self.current());
+                return;
+            }
+            Statement::Pop => {
+                if self.stack.len() > 1 {
+                    self.stack.pop();
+                }
+                return;
+            }
+            _ => return,
+        };
+        // statements listed later act first
+        let top = self.stack.last_mut().unwrap();
+        *top = op.then(top);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_parses_back_to_the_same_description() {
+        let text = r##"
+render width 64 height 48 spp 4 depth 8 seed 3
+camera from 13 2 3 at 0 0 0 up 0 1 0 fov 20 aperture 0.1 focus 10.5
+background hdr "C:\skies\my sky.hdr" rotation 90 intensity 0.25
+material "brushed steel" metal 0.7 0.6 0.5 fuzz 0.1
+material "say \"cheese\"" lambertian 0.1 0.2 0.3
+material "back\\slash" dielectric 1.5
+material "#hash" light 4 4 4
+material plain lambertian 0.5 0.5 0.5
+sphere "brushed steel" 0 -1000 0 1000
+push
+translate 0 1 0
+rotate y 45
+scale 2
+triangle "say \"cheese\"" -1 0 0  1 0 0  0 1 0
+scale 1 2 3
+quad "#hash" -1 0 -1  2 0 0  0 0 2
+pop
+box "back\\slash" 0 0 0  1 1 1
+sphere plain 0.1 0.2 0.3 0.7
+"##;
+        let description = parse_scene(text, "test.scene").unwrap();
+        assert_eq!(description.statements.len(), 19);
+        assert_eq!(description.statements[3], Statement::Material(
+            "brushed steel".to_string(), MaterialDesc::Metal(Color3::new(0.7, 0.6, 0.5), 0.1)));
+        assert_eq!(description.statements[2], Statement::Background(BackgroundDesc::Hdr {
+            path: "C:\\skies\\my sky.hdr".to_string(), rotation: 90.0, intensity: 0.25 }));
+        match &description.statements[4] {
+            Statement::Material(name, _) => assert_eq!(name, "say \"cheese\""),
+            other => panic!("unexpected {:?}", other),
+        }
+        match &description.statements[5] {
+            Statement::Material(name, _) => assert_eq!(name, "back\\slash"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let printed = description.to_string();
+        assert_eq!(parse_scene(&printed, "printed.scene").unwrap(), description);
+    }
+
+    #[test]
+    fn quoted_names_escape_quotes_and_backslashes() {
+        assert_eq!(quoted("plain"), "plain");
+        assert_eq!(quoted("C:\\sky.hdr"), "C:\\sky.hdr");
+        assert_eq!(quoted(""), "\"\"");
+        assert_eq!(quoted("two words"), "\"two words\"");
+        assert_eq!(quoted("a\"b"), "\"a\\\"b\"");
+        assert_eq!(quoted("a b\\"), "\"a b\\\\\"");
+        for name in ["", "two words", "a\"b", "a b\\", "#x", "\\\"", "\"quoted\""].iter() {
+            let tokens = tokenize(&quoted(name)).unwrap();
+            assert_eq!(tokens.len(), 1);
+            assert_eq!(tokens[0].text, *name);
+        }
+    }
+
+    fn error(text: &str) -> SceneError {
+        parse_scene(text, "bad.scene").expect_err(text)
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let cases = [
+            ("frobnicate 1 2", 1, 1, "unknown statement frobnicate"),
+            ("\n\n  render width 0", 3, 16, "image width"),
+            ("render width", 1, 13, "image width"),
+            ("camera from 1 2 x", 1, 17, "x"),
+            ("material m lambertian 1 1 1\nsphere q 0 0 0 1", 2, 8, "unknown material q"),
+            ("material m lambertian 1 1 1\nmaterial m metal 1 1 1", 2, 10, "already defined"),
+            ("material m glass 1.5", 1, 12, "unknown material kind glass"),
+            ("material m dielectric -1", 1, 23, "must be positive"),
+            ("pop", 1, 1, "pop without a matching push"),
+            ("push\n  scale 1 0 1", 2, 3, "can not be zero"),
+            ("rotate w 10", 1, 8, "rotation axis"),
+            ("background hdr \"sky.hdr", 1, 16, "unterminated quote"),
+            ("background sky extra # comment", 1, 16, "extra"),
+        ];
+        for (text, line, column, message) in cases.iter() {
+            let e = error(text);
+            assert_eq!((e.line, e.column), (*line, *column), "{}", e);
+            assert!(e.message.contains(message), "{}", e);
+            assert_eq!(e.file, "bad.scene");
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use crate::image::Image;
+use crate::m::clamp;
+use crate::perlin::Perlin;
+use crate::sampler::Sampler;
+use crate::vectors::{Axis, Color3, Point3};
+
+/// Color of a surface at texture coordinates `(u, v)` and point `p`.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SolidColor {
+    color: Color3
+}
+
+impl SolidColor {
+    pub fn new(c: &Color3) -> SolidColor {
+        SolidColor { color: *c }
+    }
+
+    pub fn new_f(r: f64, g: f64, b: f64) -> SolidColor {
+        SolidColor { color: Color3::new(r, g, b) }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color3 {
+        self.color
+    }
+}
+
+/// Checkerboard in texture space with `scale` squares per unit of `u` and `v`.
+pub struct Checker2D {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl Checker2D {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f64) -> Checker2D {
+        Checker2D { even, odd, scale }
+    }
+
+    pub fn new_c(even: &Color3, odd: &Color3, scale: f64) -> Checker2D {
+        Checker2D::new(Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)), scale)
+    }
+}
+
+impl Texture for Checker2D {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let sum = (u * self.scale).floor() as i64 + (v * self.scale).floor() as i64;
+        if sum.rem_euclid(2) == 0 { self.even.value(u, v, p) } else { self.odd.value(u, v, p) }
+    }
+}
+
+/// Checkerboard of cubes in space, `scale` cubes per world unit.
+pub struct Checker3D {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl Checker3D {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f64) -> Checker3D {
+        Checker3D { even, odd, scale }
+    }
+
+    pub fn new_c(even: &Color3, odd: &Color3, scale: f64) -> Checker3D {
+        Checker3D::new(Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)), scale)
+    }
+}
+
+impl Texture for Checker3D {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+        let sum = (p[Axis::X] * self.scale).floor() as i64
+            + (p[Axis::Y] * self.scale).floor() as i64
+            + (p[Axis::Z] * self.scale).floor() as i64;
+        if sum.rem_euclid(2) == 0 { self.even.value(u, v, p) } else { self.odd.value(u, v, p) }
+    }
+}
+
+/// Image mapped over the unit square of texture space, `v = 0` being the bottom row.
+pub struct ImageTexture {
+    image: Image
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> ImageTexture {
+        ImageTexture { image }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color3 {
+        let w = self.image.width();
+        let h = self.image.height();
+        if w == 0 || h == 0 {
+            return Color3::new(0.0, 1.0, 1.0);
+        }
+
+        let u = clamp(u, 0.0, 1.0);
+        let v = 1.0 - clamp(v, 0.0, 1.0);
+        let x = ((u * w as f64) as usize).min(w - 1);
+        let y = ((v * h as f64) as usize).min(h - 1);
+        self.image.get(x, y)
+    }
+}
+
+/// Shape given to Perlin noise by a `NoiseTexture`.
+#[derive(Debug, Copy, Clone)]
+pub enum NoisePattern {
+    /// Plain smooth noise.
+    Noise,
+    /// Fractal sum of absolute noise octaves.
+    Turbulence,
+    /// Fractal sum of signed noise octaves, softer than turbulence.
+    Fbm,
+    /// Parallel veins along Z distorted by turbulence.
+    Marble,
+    /// Growth rings around the Y axis distorted by turbulence.
+    Wood,
+}
+
+const TURBULENCE_DEPTH: u32 = 7;
+
+/// Procedural texture blending from `dark` to `light` according to a noise pattern,
+/// `scale` being the noise frequency per world unit.
+pub struct NoiseTexture {
+    noise: Perlin,
+    pattern: NoisePattern,
+    scale: f64,
+    light: Color3,
+    dark: Color3,
+}
+
+impl NoiseTexture {
+    pub fn new(rng: &mut Sampler, pattern: NoisePattern, scale: f64) -> NoiseTexture {
+        NoiseTexture::with_colors(rng, pattern, scale, &Color3::new(1.0, 1.0, 1.0), &Color3::zero())
+    }
+
+    pub fn with_colors(rng: &mut Sampler, pattern: NoisePattern, scale: f64, light: &Color3, dark: &Color3) -> NoiseTexture {
+        NoiseTexture { noise: Perlin::new(rng), pattern, scale, light: *light, dark: *dark }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color3 {
+        let sp = self.scale * *p;
+        let t = match self.pattern {
+            NoisePattern::Noise => 0.5 * (1.0 + self.noise.noise(&sp)),
+            NoisePattern::Turbulence => self.noise.turb(&sp, TURBULENCE_DEPTH),
+            NoisePattern::Fbm => 0.5 * (1.0 + self.noise.fbm(&sp, TURBULENCE_DEPTH)),
+            NoisePattern::Marble => 0.5 * (1.0 + (sp[Axis::Z] + 10.0 * self.noise.turb(&sp, TURBULENCE_DEPTH)).sin()),
+            NoisePattern::Wood => {
+                let r = (sp[Axis::X] * sp[Axis::X] + sp[Axis::Z] * sp[Axis::Z]).sqrt();
+                let rings = 8.0 * r + 2.0 * self.noise.turb(&sp, TURBULENCE_DEPTH);
+                rings - rings.floor()
+            }
+        };
+        let t = clamp(t, 0.0, 1.0);
+        (1.0 - t) * self.dark + t * self.light
+    }
+}
@@ -0,0 +1,345 @@
+use std::sync::{Arc, OnceLock};
+
+use crate::image::Image;
+use crate::m::clamp;
+use crate::sampler::Sampler;
+use crate::vectors::{Color3, Colors};
+
+/// Compresses linear scene radiance into the linear `[0, 1]` range of a display.
+pub trait ToneMapper: Send + Sync {
+    fn map(&self, c: &Color3) -> Color3;
+}
+
+/// No compression, values above one are clipped by the output stage.
+#[derive(Debug, Copy, Clone)]
+pub struct Linear;
+
+impl ToneMapper for Linear {
+    fn map(&self, c: &Color3) -> Color3 {
+        *c
+    }
+}
+
+/// `c / (1 + c)` on every channel.
+#[derive(Debug, Copy, Clone)]
+pub struct Reinhard;
+
+impl ToneMapper for Reinhard {
+    fn map(&self, c: &Color3) -> Color3 {
+        per_channel(c, |x| x / (1.0 + x))
+    }
+}
+
+/// Reinhard operator mapping `white` and above to full white.
+#[derive(Debug, Copy, Clone)]
+pub struct ExtendedReinhard {
+    white: f64
+}
+
+impl ExtendedReinhard {
+    pub fn new(white: f64) -> ExtendedReinhard {
+        ExtendedReinhard { white }
+    }
+}
+
+impl ToneMapper for ExtendedReinhard {
+    fn map(&self, c: &Color3) -> Color3 {
+        let w2 = self.white * self.white;
+        per_channel(c, |x| x * (1.0 + x / w2) / (1.0 + x))
+    }
+}
+
+/// Krzysztof Narkowicz's fit of the ACES filmic reference rendering transform.
+#[derive(Debug, Copy, Clone)]
+pub struct AcesFilmic;
+
+impl ToneMapper for AcesFilmic {
+    fn map(&self, c: &Color3) -> Color3 {
+        per_channel(c, |x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14))
+    }
+}
+
+/// John Hable's Uncharted 2 filmic curve, normalized so that `white` maps to one.
+#[derive(Debug, Copy, Clone)]
+pub struct Hable {
+    white: f64
+}
+
+impl Hable {
+    pub fn new(white: f64) -> Hable {
+        Hable { white }
+    }
+}
+
+impl Default for Hable {
+    fn default() -> Hable {
+        Hable::new(11.2)
+    }
+}
+
+impl ToneMapper for Hable {
+    fn map(&self, c: &Color3) -> Color3 {
+        let white_scale = 1.0 / hable_partial(self.white);
+        per_channel(c, |x| hable_partial(x) * white_scale)
+    }
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn per_channel<F: Fn(f64) -> f64>(c: &Color3, f: F) -> Color3 {
+    Color3::new(f(c[Colors::R].max(0.0)), f(c[Colors::G].max(0.0)), f(c[Colors::B].max(0.0)))
+}
+
+/// Exact sRGB opto-electronic transfer function.
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        // same as 1.055 x^(1/2.4) - 0.055, arranged so that one stays exactly one
+        1.055 * (x.powf(1.0 / 2.4) - 1.0) + 1.0
+    }
+}
+
+/// Noise added before quantization to break up banding in smooth gradients.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dither {
+    None,
+    /// 8x8 Bayer matrix.
+    Ordered,
+    /// 32x32 void-and-cluster mask.
+    BlueNoise,
+}
+
+/// Turns linear radiance into display values: exposure (in stops), tone
+/// mapping operator, sRGB encoding and optional dithering.
+pub struct ToneMapping {
+    pub exposure: f64,
+    pub operator: Arc<dyn ToneMapper>,
+    pub dither: Dither,
+}
+
+impl ToneMapping {
+    pub fn new(operator: Arc<dyn ToneMapper>) -> ToneMapping {
+        ToneMapping { exposure: 0.0, operator, dither: Dither::None }
+    }
+
+    /// sRGB encoded value of a single color, clamped to `[0, 1]`.
+    pub fn to_display(&self, c: &Color3) -> Color3 {
+        let mapped = self.operator.map(&(2f64.powf(self.exposure) * *c));
+        Color3::new(clamp(srgb_oetf(mapped[Colors::R]), 0.0, 1.0),
+                    clamp(srgb_oetf(mapped[Colors::G]), 0.0, 1.0),
+                    clamp(srgb_oetf(mapped[Colors::B]), 0.0, 1.0))
+    }
+
+    /// Display values of a whole image meant to be rounded to `max_level`
+    /// (255 for 8-bit output), dither is already applied.
+    pub fn apply(&self, image: &Image, max_level: u32) -> Image {
+        let mut pixels = Vec::with_capacity(image.pixels().len());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let c = self.to_display(&image.get(x, y));
+                let offset = match self.dither {
+                    Dither::None => 0.0,
+                    Dither::Ordered => bayer_threshold(x, y) - 0.5,
+                    Dither::BlueNoise => blue_noise_threshold(x, y) - 0.5,
+                } / max_level as f64;
+                pixels.push(Color3::new(clamp(c[Colors::R] + offset, 0.0, 1.0),
+                                        clamp(c[Colors::G] + offset, 0.0, 1.0),
+                                        clamp(c[Colors::B] + offset, 0.0, 1.0)));
+            }
+        }
+        Image::new(image.width(), image.height(), pixels)
+    }
+}
+
+fn bayer_threshold(x: usize, y: usize) -> f64 {
+    // builds the 8x8 index from the bits of x and y, interleaving x ^ y and y
+    let (x, y) = (x & 7, y & 7);
+    let xy = x ^ y;
+    let idx = ((xy & 1) << 5) | ((y & 1) << 4) | ((xy & 2) << 2) | ((y & 2) << 1) | ((xy & 4) >> 1) | ((y & 4) >> 2);
+    (idx as f64 + 0.5) / 64.0
+}
+
+const BLUE_NOISE_SIZE: usize = 32;
+
+fn blue_noise_threshold(x: usize, y: usize) -> f64 {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    let mask = MASK.get_or_init(void_and_cluster);
+    mask[(y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE]
+}
+
+// Ulichney's void-and-cluster method on a torus with a gaussian energy filter
+fn void_and_cluster() -> Vec<f64> {
+    let n = BLUE_NOISE_SIZE;
+    let total = n * n;
+    let sigma = 1.5;
+
+    let mut kernel = vec![0.0; total];
+    for dy in 0..n {
+        for dx in 0..n {
+            let ddx = dx.min(n - dx) as f64;
+            let ddy = dy.min(n - dy) as f64;
+            kernel[dy * n + dx] = (-(ddx * ddx + ddy * ddy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+
+    let mut energy = vec![0.0; total];
+    let update = |energy: &mut Vec<f64>, idx: usize, sign: f64| {
+        let (px, py) = (idx % n, idx / n);
+        for y in 0..n {
+            for x in 0..n {
+                let k = ((y + n - py) % n) * n + (x + n - px) % n;
+                energy[y * n + x] += sign * kernel[k];
+            }
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| -> usize {
+        (0..total).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| -> usize {
+        (0..total).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    // initial pattern: a tenth of the cells picked at random, then relaxed
+    let mut rng = Sampler::new(0x5EED);
+    let mut pattern = vec![false; total];
+    let ones = total / 10;
+    let mut placed = 0;
+    while placed < ones {
+        let idx = ((rng.rand_f() * total as f64) as usize).min(total - 1);
+        if !pattern[idx] {
+            pattern[idx] = true;
+            update(&mut energy, idx, 1.0);
+            placed += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        if void == cluster {
+            pattern[cluster] = true;
+            update(&mut energy, cluster, 1.0);
+            break;
+        }
+        pattern[void] = true;
+        update(&mut energy, void, 1.0);
+    }
+
+    let mut rank = vec![0usize; total];
+
+    // ranks below the initial pattern: remove the tightest clusters one by one
+    let mut work = pattern.clone();
+    let mut work_energy = energy.clone();
+    for r in (0..ones).rev() {
+        let cluster = tightest_cluster(&work, &work_energy);
+        work[cluster] = false;
+        update(&mut work_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // ranks above it: keep filling the largest voids
+    for r in ones..total {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r as f64 + 0.5) / total as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operators() -> Vec<(&'static str, Arc<dyn ToneMapper>)> {
+        vec![("linear", Arc::new(Linear)),
+             ("reinhard", Arc::new(Reinhard)),
+             ("extended reinhard", Arc::new(ExtendedReinhard::new(4.0))),
+             ("aces", Arc::new(AcesFilmic)),
+             ("hable", Arc::new(Hable::default()))]
+    }
+
+    fn gray(x: f64) -> Color3 {
+        Color3::new(x, x, x)
+    }
+
+    #[test]
+    fn srgb_curve_is_continuous_and_ends_at_one() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert_eq!(srgb_oetf(1.0), 1.0);
+        // both pieces meet at the seam
+        let seam = 0.0031308;
+        assert!((srgb_oetf(seam) - 12.92 * seam).abs() < 1e-12);
+        assert!((srgb_oetf(seam) - (1.055 * seam.powf(1.0 / 2.4) - 0.055)).abs() < 1e-6);
+        assert!((srgb_oetf(seam + 1e-9) - srgb_oetf(seam)).abs() < 1e-6);
+        assert!((srgb_oetf(0.5) - 0.735_356_983_052_449).abs() < 1e-9);
+    }
+
+    #[test]
+    fn operators_are_monotonic_and_start_at_black() {
+        for (name, op) in operators() {
+            assert_eq!(op.map(&Color3::zero()), Color3::zero(), "{}", name);
+            if name != "linear" {
+                // negative radiance is treated as black
+                assert_eq!(op.map(&gray(-2.0)), Color3::zero(), "{}", name);
+            }
+            let mut previous = 0.0;
+            for i in 1..=2000 {
+                let y = op.map(&gray(i as f64 * 0.01))[Colors::G];
+                assert!(y > previous, "{} drops at {}", name, i as f64 * 0.01);
+                previous = y;
+            }
+        }
+    }
+
+    #[test]
+    fn operators_map_their_white_point_to_one() {
+        assert_eq!(Linear.map(&gray(1.0)), gray(1.0));
+        assert!((ExtendedReinhard::new(4.0).map(&gray(4.0))[Colors::R] - 1.0).abs() < 1e-12);
+        assert!((Hable::default().map(&gray(11.2))[Colors::R] - 1.0).abs() < 1e-12);
+        assert!((Hable::new(6.0).map(&gray(6.0))[Colors::R] - 1.0).abs() < 1e-12);
+        // the asymptotic ones approach white without overshooting it early
+        assert!((Reinhard.map(&gray(1e9))[Colors::R] - 1.0).abs() < 1e-6);
+        assert!(Reinhard.map(&gray(100.0))[Colors::R] < 1.0);
+        assert!(AcesFilmic.map(&gray(10.0))[Colors::R] > 0.98);
+        assert!(AcesFilmic.map(&gray(1.0))[Colors::R] < 0.85);
+    }
+
+    #[test]
+    fn exposure_is_counted_in_stops() {
+        let mut tm = ToneMapping::new(Arc::new(Linear));
+        tm.exposure = -1.0;
+        assert_eq!(tm.to_display(&gray(0.5)), gray(srgb_oetf(0.25)));
+        tm.exposure = 2.0;
+        assert_eq!(tm.to_display(&gray(0.5)), gray(1.0));
+    }
+
+    fn assert_permutation(thresholds: Vec<f64>) {
+        let n = thresholds.len();
+        let mut sorted = thresholds;
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        for (i, t) in sorted.iter().enumerate() {
+            assert!(*t > 0.0 && *t < 1.0);
+            assert_eq!(*t, (i as f64 + 0.5) / n as f64);
+        }
+    }
+
+    #[test]
+    fn bayer_thresholds_are_a_permutation() {
+        assert_permutation((0..64).map(|i| bayer_threshold(i % 8, i / 8)).collect());
+        assert_eq!(bayer_threshold(3, 5), bayer_threshold(11, 21));
+    }
+
+    #[test]
+    fn blue_noise_thresholds_are_a_permutation() {
+        let n = BLUE_NOISE_SIZE;
+        assert_permutation((0..n * n).map(|i| blue_noise_threshold(i % n, i / n)).collect());
+        assert_eq!(blue_noise_threshold(3, 5), blue_noise_threshold(3 + n, 5 + 2 * n));
+    }
+}
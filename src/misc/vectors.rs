@@ -1,8 +1,9 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub};
 
-use crate::m::{rand_f, rand_f_mm};
+use crate::sampler::Sampler;
 
 //Axis
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Axis {
     X,
     Y,
@@ -17,7 +18,7 @@ pub enum Colors {
     B,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec3 {
     e: [f64; 3]
 }
@@ -34,6 +35,14 @@ impl Index<Axis> for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.e[index]
+    }
+}
+
 impl Index<Colors> for Vec3 {
     type Output = f64;
 
@@ -146,18 +155,18 @@ impl Vec3 {
         Vec3 { e: [x, y, z] }
     }
 
-    pub fn random() -> Vec3 {
-        Vec3::new(rand_f(), rand_f(), rand_f())
+    pub fn random(rng: &mut Sampler) -> Vec3 {
+        Vec3::new(rng.rand_f(), rng.rand_f(), rng.rand_f())
     }
 
-    pub fn random_mm(min: f64, max: f64) -> Vec3 {
-        Vec3::new(rand_f_mm(min, max), rand_f_mm(min, max), rand_f_mm(min, max))
+    pub fn random_mm(rng: &mut Sampler, min: f64, max: f64) -> Vec3 {
+        Vec3::new(rng.rand_f_mm(min, max), rng.rand_f_mm(min, max), rng.rand_f_mm(min, max))
     }
 
-    pub fn rand_in_unit_sphere() -> Vec3 {
+    pub fn rand_in_unit_sphere(rng: &mut Sampler) -> Vec3 {
         let mut vec = Option::None;
         while vec.is_none() {
-            let p = Vec3::random_mm(-1.0, 1.0);
+            let p = Vec3::random_mm(rng, -1.0, 1.0);
             if p.length_squared() < 1.0 {
                 vec = Some(p)
             }
@@ -165,10 +174,10 @@ impl Vec3 {
         vec.unwrap()
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
+    pub fn random_in_unit_disk(rng: &mut Sampler) -> Vec3 {
         let mut vec = Option::None;
         while vec.is_none() {
-            let p = Vec3::new(rand_f_mm(-1.0, 1.0), rand_f_mm(-1.0, 1.0), 0.0);
+            let p = Vec3::new(rng.rand_f_mm(-1.0, 1.0), rng.rand_f_mm(-1.0, 1.0), 0.0);
             if p.length_squared() < 1.0 {
                 vec = Some(p)
             }
@@ -176,8 +185,8 @@ impl Vec3 {
         vec.unwrap()
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::rand_in_unit_sphere().unit_vec()
+    pub fn random_unit_vector(rng: &mut Sampler) -> Vec3 {
+        Vec3::rand_in_unit_sphere(rng).unit_vec()
     }
 
 
@@ -191,6 +200,10 @@ impl Vec3 {
         self.length_squared().sqrt()
     }
 
+    pub fn max_component(self) -> f64 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+
     pub fn dot(self, v: Vec3) -> f64 {
         self.e[0] * v.e[0] +
             self.e[1] * v.e[1] +
@@ -226,4 +239,281 @@ impl Vec3 {
 
 
 pub type Point3 = Vec3;
-pub type Color3 = Vec3;
\ No newline at end of file
+pub type Color3 = Vec3;
+
+/// Row-major 4x4 matrix, points are multiplied as `(x, y, z, 1)` and vectors as `(x, y, z, 0)`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4]
+}
+
+impl Index<(usize, usize)> for Mat4 {
+    type Output = f64;
+
+    fn index(&self, (row, col): (usize, usize)) -> &Self::Output {
+        &self.m[row][col]
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+impl Mat4 {
+    pub fn new(rows: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m: rows }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::scaling(&Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn translation(offset: &Vec3) -> Mat4 {
+        Mat4::new([[1.0, 0.0, 0.0, offset.e[0]],
+                   [0.0, 1.0, 0.0, offset.e[1]],
+                   [0.0, 0.0, 1.0, offset.e[2]],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn scaling(factors: &Vec3) -> Mat4 {
+        Mat4::new([[factors.e[0], 0.0, 0.0, 0.0],
+                   [0.0, factors.e[1], 0.0, 0.0],
+                   [0.0, 0.0, factors.e[2], 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    /// Counter-clockwise rotation by `degrees` when looking down `axis` towards the origin.
+    pub fn rotation(axis: &Vec3, degrees: f64) -> Mat4 {
+        let a = axis.unit_vec();
+        let (x, y, z) = (a.e[0], a.e[1], a.e[2]);
+        let (s, c) = (degrees * std::f64::consts::PI / 180.0).sin_cos();
+        let t = 1.0 - c;
+        Mat4::new([[t * x * x + c, t * x * y - s * z, t * x * z + s * y, 0.0],
+                   [t * x * y + s * z, t * y * y + c, t * y * z - s * x, 0.0],
+                   [t * x * z - s * y, t * y * z + s * x, t * z * z + c, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for k in 0..4 {
+                a[col][k] /= p;
+                inv[col][k] /= p;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= f * a[col][k];
+                        inv[row][k] -= f * inv[col][k];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    /// Determinant of the upper left 3x3 block, the volume scale of the linear part.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let row = |i: usize| self.m[i][0] * v.e[0] + self.m[i][1] * v.e[1] + self.m[i][2] * v.e[2];
+        Vec3::new(row(0), row(1), row(2))
+    }
+}
+
+/// Affine transform kept together with its inverse.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    m: Mat4,
+    inv: Mat4,
+}
+
+impl Transform {
+    /// `None` when `m` can not be inverted.
+    pub fn new(m: Mat4) -> Option<Transform> {
+        Some(Transform { m, inv: m.inverse()? })
+    }
+
+    pub fn identity() -> Transform {
+        Transform { m: Mat4::identity(), inv: Mat4::identity() }
+    }
+
+    pub fn translate(offset: &Vec3) -> Transform {
+        Transform { m: Mat4::translation(offset), inv: Mat4::translation(&-*offset) }
+    }
+
+    pub fn rotate(axis: &Vec3, degrees: f64) -> Transform {
+        let m = Mat4::rotation(axis, degrees);
+        Transform { m, inv: m.transpose() }
+    }
+
+    /// Scaling along the coordinate axes, the factors must not be zero.
+    pub fn scale(factors: &Vec3) -> Transform {
+        let inverse = Vec3::new(1.0 / factors.e[0], 1.0 / factors.e[1], 1.0 / factors.e[2]);
+        Transform { m: Mat4::scaling(factors), inv: Mat4::scaling(&inverse) }
+    }
+
+    /// Applies `self` first and `next` to the result.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform { m: next.m * self.m, inv: self.inv * next.inv }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { m: self.inv, inv: self.m }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.m
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        self.m.transform_point(p)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.m.transform_vector(v)
+    }
+
+    /// Normals go through the inverse transpose so they stay perpendicular to
+    /// the transformed surface; the result is not normalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.inv.transpose().transform_vector(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Mat4, b: &Mat4) -> bool {
+        (0..4).all(|i| (0..4).all(|j| (a[(i, j)] - b[(i, j)]).abs() < 1e-9))
+    }
+
+    fn close_vec(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrices = [
+            Mat4::identity(),
+            Mat4::translation(&Vec3::new(1.0, -2.0, 3.0)),
+            Mat4::scaling(&Vec3::new(2.0, 0.5, -4.0)),
+            Mat4::rotation(&Vec3::new(1.0, 2.0, 3.0), 37.0),
+            // needs a row swap, the first pivot is zero
+            Mat4::new([[0.0, 2.0, 0.0, 1.0],
+                       [1.0, 0.0, 0.0, 2.0],
+                       [0.0, 0.0, 3.0, 3.0],
+                       [0.0, 0.0, 0.0, 1.0]]),
+            Mat4::new([[2.0, 1.0, 1.0, 0.5],
+                       [1.0, 3.0, 2.0, -1.0],
+                       [1.0, 0.0, 0.0, 4.0],
+                       [0.5, 0.0, 1.0, 1.0]]),
+        ];
+        for m in matrices.iter() {
+            let inv = m.inverse().unwrap();
+            assert!(close(&(*m * inv), &Mat4::identity()), "{:?}", m);
+            assert!(close(&(inv * *m), &Mat4::identity()), "{:?}", m);
+        }
+        assert!(close(&Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), 90.0).inverse().unwrap(),
+                      &Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), -90.0)));
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert!(Mat4::scaling(&Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        let m = Mat4::new([[1.0, 2.0, 3.0, 0.0],
+                           [2.0, 4.0, 6.0, 0.0],
+                           [0.0, 0.0, 1.0, 0.0],
+                           [0.0, 0.0, 0.0, 1.0]]);
+        assert!(m.inverse().is_none());
+        assert!(Transform::new(m).is_none());
+    }
+
+    #[test]
+    fn rotation_is_counter_clockwise() {
+        let r = Mat4::rotation(&Vec3::new(0.0, 0.0, 1.0), 90.0);
+        assert!(close_vec(r.transform_vector(&Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 1.0, 0.0)));
+        let r = Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), 90.0);
+        assert!(close_vec(r.transform_vector(&Vec3::new(0.0, 0.0, 1.0)), Vec3::new(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let scale = Transform::scale(&Vec3::new(2.0, 3.0, 4.0));
+        let translate = Transform::translate(&Vec3::new(1.0, 0.0, -1.0));
+        let rotate = Transform::rotate(&Vec3::new(0.0, 0.0, 1.0), 90.0);
+        let p = Point3::new(1.0, 1.0, 1.0);
+
+        // scaled to (2, 3, 4), moved to (3, 3, 3), turned to (-3, 3, 3)
+        let t = scale.then(&translate).then(&rotate);
+        assert!(close_vec(t.point(&p), Point3::new(-3.0, 3.0, 3.0)));
+        assert!(close_vec(t.point(&p), rotate.point(&translate.point(&scale.point(&p)))));
+        // vectors ignore the translation
+        assert!(close_vec(t.vector(&p), Vec3::new(-3.0, 2.0, 4.0)));
+
+        // the other order moves first
+        let u = translate.then(&scale);
+        assert!(close_vec(u.point(&p), Point3::new(4.0, 3.0, 0.0)));
+
+        // grouping does not matter
+        let grouped = scale.then(&translate.then(&rotate));
+        assert!(close(grouped.matrix(), t.matrix()));
+
+        let inv = t.inverse();
+        assert!(close_vec(inv.point(&t.point(&p)), p));
+        assert!(close(&(*t.matrix() * *inv.matrix()), &Mat4::identity()));
+        assert!(close(inv.matrix(), &t.matrix().inverse().unwrap()));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let t = Transform::scale(&Vec3::new(1.0, 4.0, 1.0)).then(&Transform::rotate(&Vec3::new(1.0, 1.0, 0.0), 30.0));
+        // tangent and normal of the plane x + y = 0
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        assert!(t.vector(&tangent).dot(t.normal(&normal)).abs() < 1e-9);
+        assert!(t.vector(&Vec3::new(0.0, 0.0, 1.0)).dot(t.normal(&normal)).abs() < 1e-9);
+    }
+}
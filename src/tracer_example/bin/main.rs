@@ -1,116 +1,541 @@
-use std::f64::consts::PI;
-use std::io::{self, Stdout, Write};
-use std::rc::Rc;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
 
+use misc::bvh::BvhNode;
 use misc::camera::Camera;
+use misc::exr::{Compression, ExrImage, PixelType};
+use misc::film::Film;
 use misc::geometry::{HittableList, Sphere};
-use misc::m::{rand_f, rand_f_mm};
 use misc::materials::{Dielectric, Lambertian, Metal};
-use misc::ppm::write_color_avg;
-use misc::rays::ray_color;
-use misc::vectors::{Color3, Point3};
+use misc::png::{write_png, BitDepth};
+use misc::ppm::{write_pfm, write_ppm, PpmFormat};
+use misc::render::Renderer;
+use misc::sampler::Sampler;
+use misc::scene::{parse_scene, CameraSettings, RenderSettings, Statement};
+use misc::tonemap::{AcesFilmic, Dither, Hable, Linear, Reinhard, ToneMapper, ToneMapping};
+use misc::vectors::{Color3, Point3, Vec3};
+
+const USAGE: &str = "\
+Usage: tracer_example [OPTIONS]
+
+Renders the random spheres scene, or a scene file, and writes the image.
+Options left out keep the values of the scene.
+
+Options:
+  --scene FILE         render a scene file instead of the random spheres
+  -o, --output PATH    output image, the extension picks the format:
+                       .png, .ppm, .pfm or .exr (default: P3 text on stdout)
+  --width N            image width in pixels
+  --height N           image height in pixels
+  --aspect A           aspect ratio as 16:9 or 1.78, used with only one of
+                       --width and --height
+  --spp N              samples per pixel
+  --depth N            maximum number of bounces
+  --from X,Y,Z         camera position
+  --at X,Y,Z           point the camera looks at
+  --fov DEGREES        vertical field of view
+  --aperture A         lens aperture, 0 for a pinhole
+  --focus D            focus distance
+  --seed N             seed for a reproducible image
+  --threads N          number of render threads (default: all cores)
+  --tonemap OP         tone mapping for PNG and PPM output: linear, reinhard,
+                       aces or hable (default: linear)
+  --exposure STOPS     brighten or darken the image before tone mapping
+  -h, --help           print this help
+";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum OutputFormat {
+    Png,
+    Ppm,
+    Pfm,
+    Exr,
+    /// `P3` text, written to stdout when there is no output file.
+    PlainPpm,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ToneOperator {
+    Linear,
+    Reinhard,
+    Aces,
+    Hable,
+}
+
+#[derive(Default)]
+struct Options {
+    scene: Option<PathBuf>,
+    output: Option<PathBuf>,
+    width: Option<u32>,
+    height: Option<u32>,
+    aspect: Option<f64>,
+    samples_per_px: Option<u32>,
+    max_depth: Option<u32>,
+    lookfrom: Option<Point3>,
+    lookat: Option<Point3>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    focus_dist: Option<f64>,
+    seed: Option<u64>,
+    threads: Option<usize>,
+    tone_operator: Option<ToneOperator>,
+    exposure: Option<f64>,
+}
 
 fn main() {
-    let aspect_ratio: f64 = 5.0 / 4.0;
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("tracer_example: {}\nTry --help for the list of options.", message);
+            process::exit(2);
+        }
+    };
+    if let Err(message) = run(&options) {
+        eprintln!("tracer_example: {}", message);
+        process::exit(1);
+    }
+}
 
-    let image_width: u32 = 1200;
-    let image_height: u32 = (image_width as f64 / aspect_ratio) as u32;
+fn run(options: &Options) -> Result<(), String> {
+    let format = match &options.output {
+        Some(path) => output_format(path)?,
+        None => OutputFormat::PlainPpm,
+    };
 
-    let samples_per_px: u32 = 500;
-    let max_depth: u32 = 100;
+    let (mut renderer, camera, world) = match &options.scene {
+        Some(path) => load(path, options)?,
+        None => random_spheres(options),
+    };
+    if let Some(threads) = options.threads {
+        renderer.threads = threads;
+    }
+    renderer.progress = true;
+    let film = renderer.render(&camera, &world);
+    let tone_mapping = tone_mapping(options);
 
-    let lookfrom = Point3::new(13.0, 9.0, 7.0);
+    match &options.output {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut w = BufWriter::new(file);
+            write_image(&mut w, &film, format, &tone_mapping).and_then(|_| w.flush()).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            write_image(&mut stdout, &film, format, &tone_mapping).and_then(|_| stdout.flush()).map_err(|e| e.to_string())
+        }
+    }
+}
 
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let vup = Point3::new(0.0, 1.0, 0.0);
-    let vfov = 20.0;
-    let apperture = 0.1;
-    let focus_dist = 17.0;
+// `Ok(None)` asks for the help text
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            return Ok(None);
+        }
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--scene" => options.scene = Some(PathBuf::from(value()?)),
+            "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--width" => options.width = Some(positive(&flag, &value()?)?),
+            "--height" => options.height = Some(positive(&flag, &value()?)?),
+            "--aspect" => options.aspect = Some(aspect(&value()?)?),
+            "--spp" => options.samples_per_px = Some(positive(&flag, &value()?)?),
+            "--depth" => options.max_depth = Some(positive(&flag, &value()?)?),
+            "--from" => options.lookfrom = Some(point(&flag, &value()?)?),
+            "--at" => options.lookat = Some(point(&flag, &value()?)?),
+            "--fov" => options.vfov = Some(number(&flag, &value()?)?),
+            "--aperture" => options.aperture = Some(number(&flag, &value()?)?),
+            "--focus" => options.focus_dist = Some(number(&flag, &value()?)?),
+            "--seed" => {
+                let v = value()?;
+                options.seed = Some(v.parse().map_err(|_| format!("bad value {} for --seed", v))?);
+            }
+            "--threads" => options.threads = Some(positive(&flag, &value()?)? as usize),
+            "--tonemap" => options.tone_operator = Some(tone_operator(&value()?)?),
+            "--exposure" => options.exposure = Some(number(&flag, &value()?)?),
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    if options.width.is_some() && options.height.is_some() && options.aspect.is_some() {
+        return Err("--aspect can not be combined with both --width and --height".to_string());
+    }
+    Ok(Some(options))
+}
+
+fn positive(flag: &str, v: &str) -> Result<u32, String> {
+    match v.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} needs a positive integer, got {}", flag, v)),
+    }
+}
 
-    let camera = Camera::new(lookfrom, lookat, vup, vfov, aspect_ratio, apperture, focus_dist);
+fn number(flag: &str, v: &str) -> Result<f64, String> {
+    match v.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(format!("bad number {} for {}", v, flag)),
+    }
+}
+
+fn point(flag: &str, v: &str) -> Result<Point3, String> {
+    let parts: Vec<&str> = v.split(',').collect();
+    if parts.len() != 3 {
+        return Err(format!("{} needs X,Y,Z, got {}", flag, v));
+    }
+    Ok(Point3::new(number(flag, parts[0])?, number(flag, parts[1])?, number(flag, parts[2])?))
+}
+
+fn aspect(v: &str) -> Result<f64, String> {
+    let ratio = match v.split_once(':') {
+        Some((w, h)) => number("--aspect", w)? / number("--aspect", h)?,
+        None => number("--aspect", v)?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("bad aspect ratio {}", v))
+    }
+}
 
-    let world = random_world();
+fn tone_operator(v: &str) -> Result<ToneOperator, String> {
+    match v.to_ascii_lowercase().as_str() {
+        "linear" => Ok(ToneOperator::Linear),
+        "reinhard" => Ok(ToneOperator::Reinhard),
+        "aces" => Ok(ToneOperator::Aces),
+        "hable" => Ok(ToneOperator::Hable),
+        _ => Err(format!("unknown tone mapping {}, use linear, reinhard, aces or hable", v)),
+    }
+}
 
-    let stdout: &mut Stdout = &mut io::stdout();
-    let stderr = &mut io::stderr();
+fn output_format(path: &Path) -> Result<OutputFormat, String> {
+    let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => Ok(OutputFormat::Png),
+        Some("ppm") => Ok(OutputFormat::Ppm),
+        Some("pfm") => Ok(OutputFormat::Pfm),
+        Some("exr") => Ok(OutputFormat::Exr),
+        _ => Err(format!("can not tell the image format of {}, use .png, .ppm, .pfm or .exr", path.display())),
+    }
+}
 
-    write!(stdout, "P3\n{} {}\n255\n", image_width, image_height).unwrap();
+fn tone_mapping(options: &Options) -> ToneMapping {
+    let operator: Arc<dyn ToneMapper> = match options.tone_operator.unwrap_or(ToneOperator::Linear) {
+        ToneOperator::Linear => Arc::new(Linear),
+        ToneOperator::Reinhard => Arc::new(Reinhard),
+        ToneOperator::Aces => Arc::new(AcesFilmic),
+        ToneOperator::Hable => Arc::new(Hable::default()),
+    };
+    let mut tone_mapping = ToneMapping::new(operator);
+    tone_mapping.exposure = options.exposure.unwrap_or(0.0);
+    tone_mapping.dither = Dither::BlueNoise;
+    tone_mapping
+}
 
-    for j in (1..image_height - 1).rev() {
-        writeln!(stderr, "Scanlines remaining {}", j).unwrap();
-        for i in 0..image_width {
-            let mut color_px = Color3::zero();
+// PNG and both PPM flavours get tone mapped 8-bit values, PFM and EXR keep the linear radiance
+fn write_image<W: Write>(w: &mut W, film: &Film, format: OutputFormat, tone_mapping: &ToneMapping) -> io::Result<()> {
+    let image = film.to_image();
+    match format {
+        OutputFormat::Png => {
+            let display = tone_mapping.apply(&image, 255);
+            write_png(w, film.width(), film.height(), display.pixels(), BitDepth::Eight)
+        }
+        OutputFormat::Ppm => write_ppm(w, &tone_mapping.apply(&image, 255), PpmFormat::Binary),
+        OutputFormat::PlainPpm => write_ppm(w, &tone_mapping.apply(&image, 255), PpmFormat::Ascii),
+        OutputFormat::Pfm => write_pfm(w, &image),
+        OutputFormat::Exr => {
+            let mut exr = ExrImage::new(image.width(), image.height());
+            exr.add_layer("", &image, PixelType::Half);
+            exr.write(w, Compression::Rle)
+        }
+    }
+}
 
-            for _s in 0..samples_per_px {
-                let u = (i as f64 + rand_f()) / (image_width as f64 - 1.0);
-                let v = (j as f64 + rand_f()) / (image_height as f64 - 1.0);
-                let r = camera.get_ray(u, v);
-                color_px += ray_color(&r, &world, max_depth);
+// image size from the flags, missing values follow the aspect ratio of `settings`
+fn apply_render_options(settings: &mut RenderSettings, options: &Options) {
+    let aspect = options.aspect.unwrap_or(settings.width as f64 / settings.height as f64);
+    match (options.width, options.height) {
+        (Some(w), Some(h)) => {
+            settings.width = w;
+            settings.height = h;
+        }
+        (Some(w), None) => {
+            settings.width = w;
+            settings.height = ((w as f64 / aspect) as u32).max(1);
+        }
+        (None, Some(h)) => {
+            settings.width = ((h as f64 * aspect) as u32).max(1);
+            settings.height = h;
+        }
+        (None, None) => {
+            if options.aspect.is_some() {
+                settings.height = ((settings.width as f64 / aspect) as u32).max(1);
             }
-            write_color_avg(stdout, &color_px, samples_per_px);
         }
     }
+    settings.samples_per_px = options.samples_per_px.unwrap_or(settings.samples_per_px);
+    settings.max_depth = options.max_depth.unwrap_or(settings.max_depth);
+    settings.seed = options.seed.or(settings.seed);
+}
+
+fn apply_camera_options(camera: &mut CameraSettings, options: &Options) {
+    camera.lookfrom = options.lookfrom.unwrap_or(camera.lookfrom);
+    camera.lookat = options.lookat.unwrap_or(camera.lookat);
+    camera.vfov = options.vfov.unwrap_or(camera.vfov);
+    camera.aperture = options.aperture.unwrap_or(camera.aperture);
+    camera.focus_dist = options.focus_dist.or(camera.focus_dist);
+}
+
+// the flags are appended to the scene as `render` and `camera` statements,
+// which override the ones written in the file
+fn load(path: &Path, options: &Options) -> Result<(Renderer, Camera, BvhNode), String> {
+    let file = path.display().to_string();
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", file, e))?;
+    let mut description = parse_scene(&text, &file).map_err(|e| e.to_string())?;
+
+    let mut settings = RenderSettings::default();
+    let mut camera = CameraSettings::default();
+    for statement in description.statements.iter() {
+        match statement {
+            Statement::Render(s) => settings = *s,
+            Statement::Camera(c) => camera = *c,
+            _ => {}
+        }
+    }
+    apply_render_options(&mut settings, options);
+    apply_camera_options(&mut camera, options);
+    description.statements.push(Statement::Render(settings));
+    description.statements.push(Statement::Camera(camera));
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let scene = description.build(base_dir).map_err(|e| e.to_string())?;
+    if scene.world.elements().is_empty() {
+        return Err(format!("{}: the scene is empty", file));
+    }
+    Ok((scene.renderer(), scene.camera, BvhNode::new(&scene.world)))
 }
 
+fn random_spheres(options: &Options) -> (Renderer, Camera, BvhNode) {
+    let mut settings = RenderSettings { width: 1200, height: 960, samples_per_px: 500, max_depth: 100, seed: None };
+    let mut camera = CameraSettings {
+        lookfrom: Point3::new(13.0, 9.0, 7.0),
+        lookat: Point3::new(0.0, 0.0, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        aperture: 0.1,
+        focus_dist: Some(17.0),
+    };
+    apply_render_options(&mut settings, options);
+    apply_camera_options(&mut camera, options);
+
+    let aspect_ratio = settings.width as f64 / settings.height as f64;
+    let focus_dist = camera.focus_dist.unwrap_or(17.0);
+    let camera = Camera::new(camera.lookfrom, camera.lookat, camera.vup, camera.vfov, aspect_ratio, camera.aperture, focus_dist);
 
-pub fn random_world() -> HittableList {
+    let mut rng = match settings.seed {
+        Some(seed) => Sampler::new(seed),
+        None => Sampler::from_entropy(),
+    };
+    let world = BvhNode::new(&random_world(&mut rng));
+
+    let mut renderer = Renderer::new(settings.width, settings.height, settings.samples_per_px, settings.max_depth);
+    renderer.seed = settings.seed;
+    (renderer, camera, world)
+}
+
+pub fn random_world(rng: &mut Sampler) -> HittableList {
     let mut world = HittableList::empty();
 
-    let ground_material = Rc::new(Lambertian::new_f(0.5, 0.5, 0.5));
-    let ground_sphere = Rc::new(Sphere::new_f(0.0, -1000.0, 0.0, 1000.0, ground_material.clone()));
+    let ground_material = Arc::new(Lambertian::new_f(0.5, 0.5, 0.5));
+    let ground_sphere = Arc::new(Sphere::new_f(0.0, -1000.0, 0.0, 1000.0, ground_material.clone()));
     world.add(ground_sphere);
 
-    let dielectric = Rc::new(Dielectric::new(1.5));
+    let dielectric = Arc::new(Dielectric::new(1.5));
 
     let p = Point3::new(4.0, 0.2, 0.0);
     for a in -30..30 {
         for b in -30..30 {
-            let choose_mat = rand_f();
-            let center = Point3::new(a as f64 + 0.9 * rand_f(), 0.2, b as f64 + 0.9 * rand_f());
+            let choose_mat = rng.rand_f();
+            let center = Point3::new(a as f64 + 0.9 * rng.rand_f(), 0.2, b as f64 + 0.9 * rng.rand_f());
 
             if (center - p).length() > 0.9 {
                 if choose_mat < 0.6 {
-                    let albedo = Color3::random() * Color3::random();
-                    let sphere_mat = Rc::new(Lambertian::new(&albedo));
-                    world.add(Rc::new(Sphere::new(&center, 0.2, sphere_mat)))
+                    let albedo = Color3::random(rng) * Color3::random(rng);
+                    let sphere_mat = Arc::new(Lambertian::new(&albedo));
+                    world.add(Arc::new(Sphere::new(&center, 0.2, sphere_mat)))
                 } else if choose_mat < 0.85 {
-                    let albedo = Color3::random_mm(0.5, 1.0);
-                    let fuzz = rand_f_mm(0.0, 0.5);
-                    let sphere_mat = Rc::new(Metal::new(&albedo, fuzz));
-                    world.add(Rc::new(Sphere::new(&center, 0.2, sphere_mat)))
+                    let albedo = Color3::random_mm(rng, 0.5, 1.0);
+                    let fuzz = rng.rand_f_mm(0.0, 0.5);
+                    let sphere_mat = Arc::new(Metal::new(&albedo, fuzz));
+                    world.add(Arc::new(Sphere::new(&center, 0.2, sphere_mat)))
                 } else {
-                    world.add(Rc::new(Sphere::new(&center, 0.2, dielectric.clone())))
+                    world.add(Arc::new(Sphere::new(&center, 0.2, dielectric.clone())))
                 }
             }
         }
     }
 
 
-    world.add(Rc::new(Sphere::new_f(0.0, 1.0, 0.0, 1.0, dielectric.clone())));
-    world.add(Rc::new(Sphere::new_f(0.0, 1.0, 0.0, -0.7, dielectric.clone())));
+    world.add(Arc::new(Sphere::new_f(0.0, 1.0, 0.0, 1.0, dielectric.clone())));
+    world.add(Arc::new(Sphere::new_f(0.0, 1.0, 0.0, -0.7, dielectric.clone())));
 
-    let material2 = Rc::new(Lambertian::rand());
-    world.add(Rc::new(Sphere::new_f(-4.0, 1.0, 0.0, 1.0, material2)));
+    let material2 = Arc::new(Lambertian::rand(rng));
+    world.add(Arc::new(Sphere::new_f(-4.0, 1.0, 0.0, 1.0, material2)));
 
-    let material3 = Rc::new(Metal::rand());
-    world.add(Rc::new(Sphere::new_f(4.0, 1.0, 0.0, 1.0, material3)));
+    let material3 = Arc::new(Metal::rand(rng));
+    world.add(Arc::new(Sphere::new_f(4.0, 1.0, 0.0, 1.0, material3)));
 
-    let material4 = Rc::new(Lambertian::rand());
-    world.add(Rc::new(Sphere::new_f(0.0, 1.0, 4.0, 1.0, material4)));
+    let material4 = Arc::new(Lambertian::rand(rng));
+    world.add(Arc::new(Sphere::new_f(0.0, 1.0, 4.0, 1.0, material4)));
 
-    world.add(Rc::new(Sphere::new_f(0.0, 1.0, -4.0, 1.0, dielectric.clone())));
+    world.add(Arc::new(Sphere::new_f(0.0, 1.0, -4.0, 1.0, dielectric.clone())));
 
-    let material6 = Rc::new(Lambertian::rand());
-    world.add(Rc::new(Sphere::new_f(4.0, 1.0, -4.0, 1.0, material6)));
+    let material6 = Arc::new(Lambertian::rand(rng));
+    world.add(Arc::new(Sphere::new_f(4.0, 1.0, -4.0, 1.0, material6)));
 
-    let material7 = Rc::new(Metal::rand());
-    world.add(Rc::new(Sphere::new_f(-4.0, 1.0, 4.0, 1.0, material7)));
+    let material7 = Arc::new(Metal::rand(rng));
+    world.add(Arc::new(Sphere::new_f(-4.0, 1.0, 4.0, 1.0, material7)));
 
-    world.add(Rc::new(Sphere::new_f(-4.0, 1.0, 4.0, 1.0, dielectric.clone())));
-
-    let material9 = Rc::new(Metal::rand());
-    world.add(Rc::new(Sphere::new_f(4.0, 1.0, -4.0, 1.0, material9)));
+    world.add(Arc::new(Sphere::new_f(-4.0, 1.0, 4.0, 1.0, dielectric.clone())));
 
+    let material9 = Arc::new(Metal::rand(rng));
+    world.add(Arc::new(Sphere::new_f(4.0, 1.0, -4.0, 1.0, material9)));
 
     world
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let options = parse(&[
+            "--scene", "cornell.scene", "-o", "out.exr", "--width", "640", "--aspect", "16:9",
+            "--spp", "32", "--depth", "12", "--from", "1,2.5,-3", "--at", "0,0,0", "--fov", "35",
+            "--aperture", "0.2", "--focus", "8", "--seed", "42", "--threads", "3", "--tonemap", "ACES",
+            "--exposure", "-1.5",
+        ]).unwrap().unwrap();
+        assert_eq!(options.scene, Some(PathBuf::from("cornell.scene")));
+        assert_eq!(options.output, Some(PathBuf::from("out.exr")));
+        assert_eq!((options.width, options.height), (Some(640), None));
+        assert_eq!(options.aspect, Some(16.0 / 9.0));
+        assert_eq!((options.samples_per_px, options.max_depth), (Some(32), Some(12)));
+        assert_eq!(options.lookfrom, Some(Point3::new(1.0, 2.5, -3.0)));
+        assert_eq!(options.lookat, Some(Point3::zero()));
+        assert_eq!((options.vfov, options.aperture, options.focus_dist), (Some(35.0), Some(0.2), Some(8.0)));
+        assert_eq!((options.seed, options.threads), (Some(42), Some(3)));
+        assert_eq!((options.tone_operator, options.exposure), (Some(ToneOperator::Aces), Some(-1.5)));
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert!(parse(&["--width", "10", "-h", "--bogus"]).unwrap().is_none());
+        assert!(parse(&[]).unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let cases: [(&[&str], &str); 11] = [
+            (&["--bogus"], "unknown option --bogus"),
+            (&["--width"], "--width needs a value"),
+            (&["--width", "0"], "positive integer"),
+            (&["--spp", "-3"], "positive integer"),
+            (&["--fov", "wide"], "bad number wide"),
+            (&["--from", "1,2"], "needs X,Y,Z"),
+            (&["--aspect", "16:0"], "bad aspect ratio"),
+            (&["--seed", "-1"], "bad value -1 for --seed"),
+            (&["--tonemap", "filmic"], "unknown tone mapping filmic"),
+            (&["--exposure", "inf"], "bad number inf"),
+            (&["--width", "4", "--height", "3", "--aspect", "2"], "can not be combined"),
+        ];
+        for (args, message) in cases.iter() {
+            let e = parse(args).err().unwrap();
+            assert!(e.contains(message), "{:?}: {}", args, e);
+        }
+    }
+
+    #[test]
+    fn size_follows_the_aspect_ratio() {
+        let mut settings = RenderSettings { width: 400, height: 200, ..RenderSettings::default() };
+        apply_render_options(&mut settings, &parse(&["--width", "800"]).unwrap().unwrap());
+        assert_eq!((settings.width, settings.height), (800, 400));
+
+        apply_render_options(&mut settings, &parse(&["--height", "300", "--aspect", "4:3"]).unwrap().unwrap());
+        assert_eq!((settings.width, settings.height), (400, 300));
+
+        apply_render_options(&mut settings, &parse(&["--aspect", "1"]).unwrap().unwrap());
+        assert_eq!((settings.width, settings.height), (400, 400));
+    }
+
+    #[test]
+    fn output_format_follows_the_extension() {
+        assert_eq!(output_format(Path::new("a/b.PNG")), Ok(OutputFormat::Png));
+        assert_eq!(output_format(Path::new("b.ppm")), Ok(OutputFormat::Ppm));
+        assert_eq!(output_format(Path::new("b.pfm")), Ok(OutputFormat::Pfm));
+        assert_eq!(output_format(Path::new("b.exr")), Ok(OutputFormat::Exr));
+        assert!(output_format(Path::new("b.jpg")).is_err());
+        assert!(output_format(Path::new("image")).is_err());
+    }
+
+    #[test]
+    fn stdout_gets_plain_text() {
+        let film = Film::new(2, 1);
+        film.add_sample(0, 0, &Color3::new(1.0, 1.0, 1.0), 1.0);
+        let mut out = Vec::new();
+        write_image(&mut out, &film, OutputFormat::PlainPpm, &tone_mapping(&Options::default())).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("P3\n2 1\n255\n"), "{}", text);
+    }
+
+    #[test]
+    fn tone_mapping_follows_the_flags() {
+        let bright = Color3::new(4.0, 4.0, 4.0);
+        let linear = tone_mapping(&parse(&[]).unwrap().unwrap());
+        assert_eq!(linear.exposure, 0.0);
+        assert_eq!(linear.to_display(&bright), Color3::new(1.0, 1.0, 1.0));
+
+        // the filmic curves keep highlights below white
+        for name in ["reinhard", "aces", "hable"] {
+            let mapped = tone_mapping(&parse(&["--tonemap", name]).unwrap().unwrap()).to_display(&bright);
+            assert!(mapped[0] > 0.5 && mapped[0] < 1.0, "{}: {:?}", name, mapped);
+        }
+
+        // two stops down bring 4 back to 1
+        let darker = tone_mapping(&parse(&["--exposure", "-2"]).unwrap().unwrap());
+        assert_eq!(darker.exposure, -2.0);
+        assert_eq!(darker.to_display(&bright), Color3::new(1.0, 1.0, 1.0));
+        assert!(darker.to_display(&Color3::new(2.0, 2.0, 2.0))[0] < 1.0);
+    }
+
+    // renders the random sphere scene built from `world_seed` with pixel samples drawn from `seed`
+    fn render_random_world(world_seed: u64, seed: u64) -> Vec<u64> {
+        let world = BvhNode::new(&random_world(&mut Sampler::new(world_seed)));
+        let camera = Camera::new(Point3::new(13.0, 9.0, 7.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 20.0, 1.5, 0.1, 17.0);
+        let mut renderer = Renderer::new(24, 16, 4, 8);
+        renderer.seed = Some(seed);
+        renderer.threads = 3;
+        let film = renderer.render(&camera, &world);
+        film.to_image().pixels().iter().flat_map(|c| (0..3).map(move |i| c[i].to_bits())).collect()
+    }
+
+    #[test]
+    fn seeded_renders_are_reproducible() {
+        let reference = render_random_world(7, 11);
+        assert_eq!(render_random_world(7, 11), reference);
+        assert_ne!(render_random_world(7, 12), reference);
+        assert_ne!(render_random_world(8, 11), reference);
+    }
+}
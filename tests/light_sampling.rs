@@ -0,0 +1,60 @@
+//! Next event estimation must not change what a scene converges to, only how
+//! fast: a diffuse floor lit by a small spherical lamp is rendered once with
+//! material sampling alone and once with the lamp in the light list.
+
+use std::sync::Arc;
+
+use misc::background::SolidColor;
+use misc::camera::Camera;
+use misc::geometry::{Hittable, HittableList, Quad, Sphere};
+use misc::materials::{DiffuseLight, Lambertian};
+use misc::render::Renderer;
+use misc::vectors::{Colors, Point3, Vec3};
+
+const LAMP_RADIUS: f64 = 0.25;
+const LAMP_HEIGHT: f64 = 2.0;
+const LAMP_RADIANCE: f64 = 20.0;
+const ALBEDO: f64 = 0.5;
+
+// Renders a tiny patch of the floor right below the lamp and returns the mean
+// of the red channel over all pixels, its standard error and the pixel variance.
+fn render_floor(use_lights: bool) -> (f64, f64, f64) {
+    let lamp: Arc<dyn Hittable> = Arc::new(Sphere::new_f(0.0, LAMP_HEIGHT, 0.0, LAMP_RADIUS,
+                                                         Arc::new(DiffuseLight::new_f(LAMP_RADIANCE, LAMP_RADIANCE, LAMP_RADIANCE))));
+    let mut world = HittableList::empty();
+    world.add(Arc::new(Quad::new(&Point3::new(-10.0, 0.0, -10.0), &Vec3::new(0.0, 0.0, 20.0), &Vec3::new(20.0, 0.0, 0.0),
+                                 Arc::new(Lambertian::new_f(ALBEDO, ALBEDO, ALBEDO)))));
+    world.add(lamp.clone());
+
+    // a one degree field of view sees the floor at almost the same point everywhere
+    let camera = Camera::new(Point3::new(0.0, 1.0, 3.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, 1.0, 0.0, 3.0);
+    let mut renderer = Renderer::new(64, 64, 4, 8);
+    renderer.seed = Some(29);
+    renderer.background = Arc::new(SolidColor::new_f(0.0, 0.0, 0.0));
+    if use_lights {
+        renderer.lights.add(lamp);
+    }
+    let film = renderer.render(&camera, &world);
+
+    let values: Vec<f64> = film.to_image().pixels().iter().map(|c| c[Colors::R]).collect();
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.0);
+    (mean, (variance / n).sqrt(), variance)
+}
+
+#[test]
+fn light_sampling_converges_to_the_same_floor_with_less_noise() {
+    let (plain, plain_err, plain_var) = render_floor(false);
+    let (nee, nee_err, nee_var) = render_floor(true);
+
+    let bound = 4.0 * (plain_err * plain_err + nee_err * nee_err).sqrt();
+    assert!((plain - nee).abs() <= bound, "material sampling gives {}, light sampling {}", plain, nee);
+
+    // a sphere of radius r at distance d gives the point below it irradiance
+    // pi L (r / d)^2, the diffuse floor sends albedo / pi of that to the camera
+    let expected = ALBEDO * LAMP_RADIANCE * (LAMP_RADIUS / LAMP_HEIGHT).powi(2);
+    assert!((nee - expected).abs() <= 4.0 * nee_err + 1e-3, "light sampling gives {}, expected {}", nee, expected);
+
+    assert!(nee_var * 10.0 < plain_var, "light sampling variance {} is not well below {}", nee_var, plain_var);
+}
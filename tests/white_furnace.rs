@@ -0,0 +1,131 @@
+//! White furnace tests: objects inside a uniformly lit environment must never
+//! return more energy than they receive, and absorbed paths must stay black.
+
+use std::sync::Arc;
+
+use misc::background::{Background, Equirectangular, SolidColor};
+use misc::camera::Camera;
+use misc::geometry::{HittableList, Sphere};
+use misc::image::Image;
+use misc::materials::{Dielectric, DiffuseLight, Empty, Lambertian, Material, Metal};
+use misc::ray::Ray;
+use misc::rays::ray_color;
+use misc::render::Renderer;
+use misc::sampler::Sampler;
+use misc::texture::Checker3D;
+use misc::vectors::{Color3, Colors, Point3, Vec3};
+
+const MAX_DEPTH: u32 = 64;
+
+// Renders a unit sphere filling the whole frame and returns the mean of the
+// red channel over all pixels together with its standard error.
+fn furnace(mat: Arc<dyn Material>, background: Arc<dyn Background>) -> (f64, f64) {
+    let mut world = HittableList::empty();
+    world.add(Arc::new(Sphere::new_f(0.0, 0.0, 0.0, 1.0, mat)));
+
+    let camera = Camera::new(Point3::new(0.0, 0.0, 4.0), Point3::zero(), Vec3::new(0.0, 1.0, 0.0), 10.0, 1.0, 0.0, 4.0);
+    let mut renderer = Renderer::new(16, 16, 16, MAX_DEPTH);
+    renderer.seed = Some(17);
+    renderer.background = background;
+    let film = renderer.render(&camera, &world);
+
+    let values: Vec<f64> = film.to_image().pixels().iter().map(|c| c[Colors::R]).collect();
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.0);
+    (mean, (variance / n).sqrt())
+}
+
+fn assert_close(what: &str, (mean, stderr): (f64, f64), expected: f64) {
+    let bound = 4.0 * stderr + 1e-9;
+    assert!((mean - expected).abs() <= bound,
+            "{}: mean {} differs from {} by more than {}", what, mean, expected, bound);
+}
+
+fn white() -> Arc<dyn Background> {
+    Arc::new(SolidColor::new_f(1.0, 1.0, 1.0))
+}
+
+fn white_panorama() -> Arc<dyn Background> {
+    Arc::new(Equirectangular::new(Image::new(8, 4, vec![Color3::new(1.0, 1.0, 1.0); 32])))
+}
+
+#[test]
+fn white_lambertian_reflects_everything() {
+    assert_close("lambertian", furnace(Arc::new(Lambertian::new_f(1.0, 1.0, 1.0)), white()), 1.0);
+}
+
+#[test]
+fn grey_lambertian_reflects_its_albedo() {
+    assert_close("lambertian 0.5", furnace(Arc::new(Lambertian::new_f(0.5, 0.5, 0.5)), white()), 0.5);
+}
+
+#[test]
+fn textured_lambertian_stays_between_its_albedos() {
+    let checker = Arc::new(Checker3D::new_c(&Color3::new(0.2, 0.2, 0.2), &Color3::new(0.8, 0.8, 0.8), 4.0));
+    let (mean, _) = furnace(Arc::new(Lambertian::textured(checker)), white());
+    assert!((0.2..=0.8).contains(&mean), "checker mean {}", mean);
+}
+
+#[test]
+fn lambertian_under_importance_sampled_panorama() {
+    assert_close("lambertian env", furnace(Arc::new(Lambertian::new_f(0.8, 0.8, 0.8)), white_panorama()), 0.8);
+}
+
+#[test]
+fn mirror_reflects_everything() {
+    assert_close("metal", furnace(Arc::new(Metal::new_f(1.0, 1.0, 1.0, 0.0)), white()), 1.0);
+}
+
+#[test]
+fn fuzzy_metal_never_gains_energy() {
+    let (mean, stderr) = furnace(Arc::new(Metal::new_f(1.0, 1.0, 1.0, 1.0)), white());
+    // directions pushed below the surface are absorbed
+    assert!(mean <= 1.0 + 4.0 * stderr, "fuzzy metal mean {}", mean);
+    assert!(mean > 0.5, "fuzzy metal mean {}", mean);
+}
+
+#[test]
+fn fuzzy_metal_matches_under_importance_sampled_panorama() {
+    let metal = Arc::new(Metal::new_f(1.0, 1.0, 1.0, 0.5));
+    let (plain, plain_err) = furnace(metal.clone(), white());
+    let (sampled, sampled_err) = furnace(metal, white_panorama());
+    let bound = 4.0 * (plain_err * plain_err + sampled_err * sampled_err).sqrt();
+    assert!((plain - sampled).abs() <= bound, "fuzzy metal {} vs {} with the panorama", plain, sampled);
+}
+
+#[test]
+fn glass_is_lossless() {
+    assert_close("dielectric", furnace(Arc::new(Dielectric::new(1.5)), white()), 1.0);
+}
+
+#[test]
+fn absorbing_material_is_black() {
+    assert_close("empty", furnace(Arc::new(Empty::get()), white()), 0.0);
+}
+
+#[test]
+fn light_returns_only_its_emission() {
+    assert_close("light", furnace(Arc::new(DiffuseLight::new_f(3.0, 3.0, 3.0)), white()), 3.0);
+}
+
+#[test]
+fn metal_scattered_below_surface_is_absorbed() {
+    let mut world = HittableList::empty();
+    world.add(Arc::new(Sphere::new_f(0.0, -1000.0, 0.0, 1000.0, Arc::new(Metal::new_f(1.0, 1.0, 1.0, 1.0)))));
+    let background = SolidColor::new_f(1.0, 1.0, 1.0);
+
+    // at grazing incidence a rough mirror sends about half of the rays into the
+    // ground: those must come back black, the rest escape to the white sky
+    let mut rng = Sampler::new(3);
+    let grazing = Ray::new(&Point3::new(0.0, 1.0, 0.0), &Vec3::new(1.0, -0.05, 0.0));
+    let mut absorbed = 0;
+    for _ in 0..1000 {
+        let c = ray_color(&grazing, &world, &HittableList::empty(), &background, MAX_DEPTH, &mut rng);
+        assert!(c[Colors::R] == 0.0 || c[Colors::R] == 1.0, "unexpected radiance {}", c[Colors::R]);
+        if c[Colors::R] == 0.0 {
+            absorbed += 1;
+        }
+    }
+    assert!(absorbed > 200 && absorbed < 800, "{} of 1000 paths absorbed", absorbed);
+}